@@ -0,0 +1,37 @@
+use regex::Regex;
+
+/// Regular expressions a series title has to match to be managed
+#[derive(Default)]
+pub struct SeriesFilter(Vec<Regex>);
+
+impl SeriesFilter {
+    pub fn new(patterns: Vec<Regex>) -> Self {
+        Self(patterns)
+    }
+
+    /// Whether any pattern matches the title, always true without patterns
+    pub fn matches(&self, title: &str) -> bool {
+        self.0.is_empty() || self.0.iter().any(|pattern| pattern.is_match(title))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+
+    use super::SeriesFilter;
+
+    #[test]
+    fn empty() {
+        assert!(SeriesFilter::default().matches("Test Show"));
+    }
+
+    #[test]
+    fn any_pattern() -> Result<(), regex::Error> {
+        let filter = SeriesFilter::new(vec![Regex::new("^Test")?, Regex::new("(?i)anime")?]);
+        assert!(filter.matches("Test Show"));
+        assert!(filter.matches("Some Anime"));
+        assert!(!filter.matches("Other Show"));
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use serde::Serialize;
+use tracing::info;
+
+/// Parse the base URL of a Sonarr, Radarr or Overseerr instance
+pub fn base_url(url: &str) -> Result<Url> {
+    let mut base_url: Url = url.parse()?;
+    // a trailing slash would otherwise end up as an empty path segment
+    base_url
+        .path_segments_mut()
+        .map_err(|()| anyhow!("url is relative"))?
+        .pop_if_empty();
+    Ok(base_url)
+}
+
+/// Append the API `prefix`, e.g. `["api", "v3"]`, and `path` to `base_url`
+pub fn api_url(base_url: &Url, prefix: &[&str], path: &str) -> Result<Url> {
+    let mut url = base_url.clone();
+    url.path_segments_mut()
+        .map_err(|()| anyhow!("url is relative"))?
+        .extend(prefix)
+        .extend(path.split('/'));
+    Ok(url)
+}
+
+/// Log instead of sending a modifying request in dry run mode
+pub fn is_dry_run(dry_run: bool, method: &str, url: &Url, body: &impl Serialize) -> bool {
+    if dry_run {
+        let body = serde_json::to_string(body).unwrap_or_default();
+        info!(%url, %body, "Dry run, not sending {method}");
+    }
+    dry_run
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn api_url() -> Result<(), Box<dyn std::error::Error>> {
+        for base_url in ["http://localhost/prefix", "http://localhost/prefix/"] {
+            let base_url = super::base_url(base_url)?;
+            let url = super::api_url(&base_url, &["api", "v3"], "series/1")?;
+            assert_eq!(url.as_str(), "http://localhost/prefix/api/v3/series/1");
+        }
+        Ok(())
+    }
+}
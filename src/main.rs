@@ -9,87 +9,552 @@ use std::{
 };
 
 use anyhow::Context as _;
-use clap::{arg, command, Parser, ValueEnum};
+use clap::{arg, command, CommandFactory, Parser, ValueEnum};
 use tokio::sync::mpsc;
-use tracing::{error, info, level_filters::LevelFilter, warn};
+use tracing::{debug, error, info, level_filters::LevelFilter, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+#[cfg(feature = "plex")]
+use crate::media_server::plex;
+#[cfg(feature = "emby")]
+use crate::media_server::{embyfin, webhook};
 use crate::{
-    media_server::{plex, MediaServer as _},
+    media_server::{mock, MediaServer as _},
     once::Seen,
 };
 
+mod filter;
+mod http;
 mod media_server;
+mod notify;
 mod once;
+mod overseerr;
 mod process;
+mod prometheus;
+mod radarr;
+mod session_log;
 mod sonarr;
+mod tls;
 
-use media_server::embyfin;
+#[cfg(not(any(feature = "emby", feature = "plex")))]
+compile_error!("at least one media server feature (`emby` or `plex`) must be enabled");
 
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// How long searches in progress may take to finish when shutting down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
     /// Media server type
-    #[arg(long, default_value = "jellyfin")]
+    #[cfg_attr(
+        feature = "emby",
+        arg(
+            long,
+            default_value = "jellyfin",
+            env = "PREFETCHARR_MEDIA_SERVER_TYPE"
+        )
+    )]
+    #[cfg_attr(
+        not(feature = "emby"),
+        arg(long, default_value = "plex", env = "PREFETCHARR_MEDIA_SERVER_TYPE")
+    )]
     media_server_type: MediaServer,
     /// Jellyfin/Emby/Plex baseurl
-    #[arg(long, alias = "jellyfin-url", value_name = "URL")]
-    media_server_url: String,
-    /// Jellyfin/Emby API key or Plex server token
     #[arg(
         long,
-        value_name = "API_KEY",
-        required_unless_present = "jellyfin_api_key",
-        env = "MEDIA_SERVER_API_KEY"
+        alias = "jellyfin-url",
+        value_name = "URL",
+        env = "PREFETCHARR_MEDIA_SERVER_URL"
+    )]
+    media_server_url: Option<String>,
+    /// PEM file with the certificate authorities to trust for the media server instead of the built-in ones
+    #[arg(long, value_name = "PATH", env = "PREFETCHARR_MEDIA_SERVER_CA_CERT")]
+    media_server_ca_cert: Option<PathBuf>,
+    /// Give up on media server requests that take longer than this
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        env = "PREFETCHARR_MEDIA_SERVER_REQUEST_TIMEOUT"
+    )]
+    media_server_request_timeout: u64,
+    /// Give up on connecting to the media server after this long
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        env = "PREFETCHARR_MEDIA_SERVER_CONNECT_TIMEOUT"
     )]
+    media_server_connect_timeout: u64,
+    /// Jellyfin/Emby API key or Plex server token
+    #[arg(long, value_name = "API_KEY", env = "MEDIA_SERVER_API_KEY")]
     media_server_api_key: Option<String>,
     #[arg(long, hide = true, env = "JELLYFIN_API_KEY")]
     jellyfin_api_key: Option<String>,
     /// Sonarr baseurl
-    #[arg(long, value_name = "URL")]
+    #[arg(long, value_name = "URL", env = "PREFETCHARR_SONARR_URL")]
     sonarr_url: String,
     /// Sonarr API key
     #[arg(long, value_name = "API_KEY", env = "SONARR_API_KEY")]
     sonarr_api_key: String,
+    /// Cookies to send to Sonarr, e.g. a `cf_clearance` cookie for Cloudflare
+    #[arg(
+        long = "sonarr-cookie",
+        value_name = "NAME=VALUE",
+        value_parser = parse_cookie,
+        env = "SONARR_COOKIES",
+        value_delimiter = ','
+    )]
+    sonarr_cookies: Vec<(String, String)>,
+    /// PEM file with the certificate authorities to trust for Sonarr instead of the built-in ones
+    #[arg(long, value_name = "PATH", env = "PREFETCHARR_SONARR_CA_CERT")]
+    sonarr_ca_cert: Option<PathBuf>,
+    /// PEM file with a client certificate to authenticate to Sonarr, e.g. at a reverse proxy
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "sonarr_client_key",
+        env = "PREFETCHARR_SONARR_CLIENT_CERT"
+    )]
+    sonarr_client_cert: Option<PathBuf>,
+    /// PEM file with the private key of the Sonarr client certificate
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "sonarr_client_cert",
+        env = "PREFETCHARR_SONARR_CLIENT_KEY"
+    )]
+    sonarr_client_key: Option<PathBuf>,
+    /// Keep at most this many idle connections to Sonarr open (default: unlimited)
+    #[arg(
+        long,
+        value_name = "NUM",
+        env = "PREFETCHARR_SONARR_MAX_IDLE_CONNECTIONS"
+    )]
+    sonarr_max_idle_connections: Option<usize>,
+    /// Give up on Sonarr requests that take longer than this
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        env = "PREFETCHARR_SONARR_REQUEST_TIMEOUT"
+    )]
+    sonarr_request_timeout: u64,
+    /// Give up on connecting to Sonarr after this long
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        env = "PREFETCHARR_SONARR_CONNECT_TIMEOUT"
+    )]
+    sonarr_connect_timeout: u64,
+    /// Radarr baseurl, enables prefetching movies
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "radarr_api_key",
+        env = "PREFETCHARR_RADARR_URL"
+    )]
+    radarr_url: Option<String>,
+    /// Radarr API key
+    #[arg(long, value_name = "API_KEY", env = "RADARR_API_KEY")]
+    radarr_api_key: Option<String>,
+    /// PEM file with the certificate authorities to trust for Radarr instead of the built-in ones
+    #[arg(long, value_name = "PATH", env = "PREFETCHARR_RADARR_CA_CERT")]
+    radarr_ca_cert: Option<PathBuf>,
+    /// Give up on Radarr requests that take longer than this
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        env = "PREFETCHARR_RADARR_REQUEST_TIMEOUT"
+    )]
+    radarr_request_timeout: u64,
+    /// Give up on connecting to Radarr after this long
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        env = "PREFETCHARR_RADARR_CONNECT_TIMEOUT"
+    )]
+    radarr_connect_timeout: u64,
+    /// Overseerr or Jellyseerr baseurl, request seasons there instead of searching in Sonarr
+    #[arg(
+        long,
+        value_name = "URL",
+        requires = "overseerr_api_key",
+        env = "PREFETCHARR_OVERSEERR_URL"
+    )]
+    overseerr_url: Option<String>,
+    /// Overseerr or Jellyseerr API key
+    #[arg(long, value_name = "API_KEY", env = "OVERSEERR_API_KEY")]
+    overseerr_api_key: Option<String>,
+    /// PEM file with the certificate authorities to trust for Overseerr instead of the built-in ones
+    #[arg(long, value_name = "PATH", env = "PREFETCHARR_OVERSEERR_CA_CERT")]
+    overseerr_ca_cert: Option<PathBuf>,
+    /// Give up on Overseerr requests that take longer than this
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        env = "PREFETCHARR_OVERSEERR_REQUEST_TIMEOUT"
+    )]
+    overseerr_request_timeout: u64,
+    /// Give up on connecting to Overseerr after this long
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        env = "PREFETCHARR_OVERSEERR_CONNECT_TIMEOUT"
+    )]
+    overseerr_connect_timeout: u64,
+    /// Only manage series with at least one of these Sonarr tags (default: all series)
+    #[arg(
+        long,
+        value_name = "TAG",
+        value_delimiter = ',',
+        env = "PREFETCHARR_SONARR_REQUIRED_TAGS"
+    )]
+    sonarr_required_tags: Vec<String>,
+    /// Only manage series with one of these Sonarr quality profiles (default: all profiles)
+    #[arg(
+        long,
+        value_name = "ID",
+        value_delimiter = ',',
+        env = "PREFETCHARR_SONARR_REQUIRED_QUALITY_PROFILE_IDS"
+    )]
+    sonarr_required_quality_profile_ids: Vec<i32>,
+    /// Only manage these series, by exact title or TVDB ID (default: all series)
+    #[arg(
+        long,
+        value_name = "SERIES",
+        value_delimiter = ',',
+        env = "PREFETCHARR_SERIES"
+    )]
+    series: Vec<String>,
+    /// Only manage series with a title matching one of these regular expressions, repeat for
+    /// several patterns (default: all series)
+    #[arg(
+        long = "series-pattern",
+        value_name = "REGEX",
+        value_parser = regex::Regex::new,
+        env = "PREFETCHARR_SERIES_PATTERN"
+    )]
+    series_patterns: Vec<regex::Regex>,
+    /// Quality profile to assign to series before searching them
+    #[arg(long, value_name = "ID", env = "PREFETCHARR_SONARR_QUALITY_PROFILE_ID")]
+    sonarr_quality_profile_id: Option<i32>,
+    /// Wait for Sonarr to finish each season search (default: 0, do not wait)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 0,
+        env = "PREFETCHARR_SONARR_SEARCH_TIMEOUT"
+    )]
+    sonarr_search_timeout: u64,
+    /// Maximum number of season searches to run in Sonarr at the same time, each until Sonarr
+    /// completed it (default: unlimited)
+    #[arg(
+        long,
+        value_name = "NUM",
+        env = "PREFETCHARR_SONARR_MAX_CONCURRENT_SEARCHES"
+    )]
+    sonarr_max_concurrent_searches: Option<std::num::NonZeroUsize>,
+    /// Send at most this many requests per second to Sonarr, e.g. 2.0 (default: unlimited)
+    #[arg(long, value_name = "PER_SECOND", env = "PREFETCHARR_SONARR_RATE_LIMIT")]
+    sonarr_rate_limit: Option<f64>,
+    /// Retry requests this often while Sonarr is unavailable, e.g. restarting
+    #[arg(
+        long,
+        value_name = "NUM",
+        default_value_t = 3,
+        env = "PREFETCHARR_SONARR_REQUEST_RETRIES"
+    )]
+    sonarr_request_retries: u32,
+    /// Wait this long before retrying a Sonarr request, doubling the delay with every attempt
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 2,
+        env = "PREFETCHARR_SONARR_RETRY_DELAY"
+    )]
+    sonarr_retry_delay: u64,
+    /// Wait at most this long between retries of a Sonarr request (default: no limit)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        env = "PREFETCHARR_SONARR_MAX_RETRY_DELAY"
+    )]
+    sonarr_max_retry_delay: Option<u64>,
+    /// Let Sonarr rescan a series on disk a minute after searching it
+    #[arg(long, env = "PREFETCHARR_RESCAN_AFTER_SEARCH")]
+    rescan_after_search: bool,
+    /// Let Sonarr rename a series once the episodes of a searched season were downloaded
+    #[arg(long, env = "PREFETCHARR_RENAME_AFTER_DOWNLOAD")]
+    rename_after_download: bool,
+    /// Receive sessions through webhooks on this port instead of polling (Jellyfin and Plex)
+    #[arg(
+        long,
+        value_name = "PORT",
+        requires = "webhook_token",
+        env = "PREFETCHARR_WEBHOOK_PORT"
+    )]
+    webhook_port: Option<u16>,
+    /// Listen for webhooks on this address, e.g. 0.0.0.0 to receive them from other hosts
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        default_value = "127.0.0.1",
+        env = "PREFETCHARR_WEBHOOK_ADDRESS"
+    )]
+    webhook_address: std::net::IpAddr,
+    /// Secret that webhooks have to carry, as `token` query parameter or `X-Prefetcharr-Token` header
+    #[arg(long, value_name = "TOKEN", env = "PREFETCHARR_WEBHOOK_TOKEN")]
+    webhook_token: Option<String>,
+    /// Also treat the "Next Up" and "Continue Watching" episodes of Jellyfin and Emby users as playing
+    #[cfg(feature = "emby")]
+    #[arg(
+        long,
+        conflicts_with = "webhook_port",
+        env = "PREFETCHARR_INCLUDE_NEXT_UP"
+    )]
+    include_next_up: bool,
+    /// Treat the Plex "On Deck" list as playing, instead of the live sessions
+    #[cfg(feature = "plex")]
+    #[arg(
+        long,
+        conflicts_with = "webhook_port",
+        env = "PREFETCHARR_PLEX_ON_DECK"
+    )]
+    plex_on_deck: bool,
     /// Polling interval
-    #[arg(long, value_name = "SECONDS", default_value_t = 900)]
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 900,
+        env = "PREFETCHARR_INTERVAL"
+    )]
     interval: u64,
+    /// Directory to keep state in across restarts
+    #[arg(long, value_name = "PATH", env = "PREFETCHARR_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+    /// How long to remember searched seasons, to not search them again
+    #[arg(
+        long,
+        value_name = "HOURS",
+        default_value_t = 168,
+        env = "PREFETCHARR_SEEN_RETENTION_HOURS"
+    )]
+    seen_retention_hours: u64,
+    /// Serve Prometheus metrics on this port
+    #[arg(long, value_name = "PORT", env = "PREFETCHARR_METRICS_PORT")]
+    metrics_port: Option<u16>,
     /// Logging directory
-    #[arg(long)]
+    #[arg(long, env = "PREFETCHARR_LOG_DIR")]
     log_dir: Option<PathBuf>,
     /// The last <NUM> episodes trigger a search
-    #[arg(long, value_name = "NUM", default_value_t = 2)]
+    #[arg(
+        long,
+        value_name = "NUM",
+        default_value_t = 2,
+        env = "PREFETCHARR_REMAINING_EPISODES"
+    )]
     remaining_episodes: u8,
     /// User IDs or names to monitor episodes for (default: empty/all users)
     ///
-    /// Each entry here is checked against the user's ID and name
-    #[arg(long, value_name = "USER", value_delimiter = ',', num_args = 0..)]
+    /// Each entry here is checked against the user's ID and name, ignoring case
+    #[arg(
+        long,
+        value_name = "USER",
+        value_delimiter = ',',
+        num_args = 0..,
+        env = "PREFETCHARR_USERS"
+    )]
     users: Vec<String>,
+    /// User IDs or names to never monitor episodes for, checked like `--users`
+    #[arg(
+        long,
+        value_name = "USER",
+        value_delimiter = ',',
+        env = "PREFETCHARR_EXCLUDE_USERS"
+    )]
+    exclude_users: Vec<String>,
+    /// Only react to sessions that played at least this percentage of the item
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 0.0,
+        value_parser = parse_percent,
+        env = "PREFETCHARR_MIN_PROGRESS"
+    )]
+    min_progress: f32,
+    /// Search all missing episodes on a schedule, e.g. "0 3 * * *" for 3 AM (UTC)
+    #[arg(
+        long,
+        value_name = "CRON",
+        value_parser = parse_cron,
+        env = "PREFETCHARR_SCHEDULED_FULL_SEARCH_CRON"
+    )]
+    scheduled_full_search_cron: Option<cron::Schedule>,
+    /// Append every incoming session as a JSON line to this file
+    #[arg(long, value_name = "PATH", env = "PREFETCHARR_SESSION_LOG")]
+    session_log: Option<PathBuf>,
+    /// POST a JSON message to these URLs after each search
+    #[arg(
+        long,
+        value_name = "URL",
+        value_delimiter = ',',
+        env = "PREFETCHARR_NOTIFICATION_URLS"
+    )]
+    notification_url: Vec<String>,
+    /// Headers to send with notifications, e.g. `Authorization: Bearer <TOKEN>`
+    #[arg(
+        long,
+        value_name = "NAME: VALUE",
+        value_parser = parse_header,
+        requires = "notification_url",
+        env = "PREFETCHARR_NOTIFICATION_HEADERS",
+        value_delimiter = ','
+    )]
+    notification_header: Vec<(String, String)>,
+    /// JSON file with sessions to replay when using the `mock` media server
+    #[arg(
+        long,
+        value_name = "PATH",
+        required_if_eq("media_server_type", "mock"),
+        env = "PREFETCHARR_MOCK_SESSIONS_FILE"
+    )]
+    mock_sessions_file: Option<PathBuf>,
+    /// Only log what would be changed in Sonarr and Radarr
+    #[arg(long, env = "PREFETCHARR_DRY_RUN")]
+    dry_run: bool,
+    /// Validate the configuration and exit
+    #[arg(long, conflicts_with = "push_release")]
+    check: bool,
+    /// Push a release to Sonarr and exit, identified by its GUID from the indexer. No media
+    /// server settings are needed for this.
+    #[arg(
+        long,
+        value_name = "GUID",
+        requires_all = ["push_release_indexer_id", "push_release_episode_id"]
+    )]
+    push_release: Option<String>,
+    /// Indexer the pushed release was found on
+    #[arg(long, value_name = "ID", requires = "push_release")]
+    push_release_indexer_id: Option<i32>,
+    /// Episode the pushed release belongs to
+    #[arg(long, value_name = "ID", requires = "push_release")]
+    push_release_episode_id: Option<i32>,
+    /// Poll the media server a single time, process the sessions and exit
+    #[arg(
+        long,
+        conflicts_with_all = ["webhook_port", "scheduled_full_search_cron"],
+        env = "PREFETCHARR_ONCE"
+    )]
+    once: bool,
+}
+
+impl Args {
+    /// Require the media server URL and API key unless they aren't used.
+    ///
+    /// Clap only compares explicitly given values, so it can't tell that the
+    /// default media server type needs them while `mock` does not.
+    fn require_media_server(&self) -> Result<(), clap::Error> {
+        if self.push_release.is_some() || matches!(self.media_server_type, MediaServer::Mock) {
+            return Ok(());
+        }
+        let missing = [
+            ("--media-server-url <URL>", self.media_server_url.is_none()),
+            (
+                "--media-server-api-key <API_KEY>",
+                self.media_server_api_key.is_none() && self.jellyfin_api_key.is_none(),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(arg, missing)| missing.then_some(arg))
+        .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Args::command().error(
+            clap::error::ErrorKind::MissingRequiredArgument,
+            format!(
+                "the following required arguments were not provided: {}",
+                missing.join(", ")
+            ),
+        ))
+    }
 }
 
 #[derive(Clone, Debug, ValueEnum)]
 enum MediaServer {
+    #[cfg(feature = "emby")]
     Jellyfin,
+    #[cfg(feature = "emby")]
     Emby,
+    #[cfg(feature = "plex")]
     Plex,
+    /// Replay sessions from a file (for testing)
+    Mock,
+}
+
+impl MediaServer {
+    fn supports_webhooks(&self) -> bool {
+        match self {
+            #[cfg(feature = "emby")]
+            Self::Jellyfin => true,
+            #[cfg(feature = "plex")]
+            Self::Plex => true,
+            _ => false,
+        }
+    }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum Message {
     NowPlaying(media_server::NowPlaying),
+    /// Playback ended early, so the item may be searched again on resume
+    #[cfg_attr(not(feature = "emby"), allow(dead_code))]
+    Stopped(media_server::NowPlaying),
+    Shutdown,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    if let Err(e) = args.require_media_server() {
+        e.exit();
+    }
 
     enable_logging(&args.log_dir);
 
     info!("{NAME} {VERSION}");
     warn_deprecated(&args);
+    log_settings(&args);
+
+    if args.check {
+        let problems = check(&args);
+        for problem in &problems {
+            error!("{problem}");
+        }
+        if !problems.is_empty() {
+            return Err(format!("{} problem(s) in the configuration", problems.len()).into());
+        }
+        info!("Configuration is valid");
+        return Ok(());
+    }
+
+    if let Some(guid) = &args.push_release {
+        if let Err(e) = push_release(&args, guid).await {
+            error!("{e:#}");
+            return Err(e.into());
+        }
+        info!("Release pushed to Sonarr");
+        return Ok(());
+    }
 
     if let Err(e) = run(args).await {
         error!("{e:#}");
@@ -103,60 +568,456 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 async fn run(args: Args) -> anyhow::Result<()> {
     let (tx, rx) = mpsc::channel(1);
 
+    if let Some(port) = args.metrics_port {
+        let handle = prometheus::install().context("Cannot record metrics")?;
+        let server = prometheus::listen(port, handle).await?;
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("{e:#}");
+            }
+        });
+    }
+
+    let shutdown_tx = tx.clone();
+    tokio::spawn(async move {
+        match shutdown_signal().await {
+            Ok(()) => {
+                info!("Received shutdown signal");
+                // the receiver is only gone if we are already shutting down
+                let _ = shutdown_tx.send(Message::Shutdown).await;
+            }
+            Err(e) => error!("Cannot listen for shutdown signals: {e}"),
+        }
+    });
+
     // backward compat
+    // only left out for the mock, see Args::require_media_server
     let media_server_api_key = args
         .media_server_api_key
-        .or(args.jellyfin_api_key)
-        .expect("using value enforced via clap");
+        .clone()
+        .or_else(|| args.jellyfin_api_key.clone())
+        .unwrap_or_default();
+    let media_server_url = args.media_server_url.clone().unwrap_or_default();
 
-    let sonarr_client = sonarr::Client::new(&args.sonarr_url, &args.sonarr_api_key)
-        .context("Invalid connection parameters for Sonarr")?;
+    let sonarr_options = sonarr_options(&args);
+    let mut sonarr_client =
+        sonarr::Client::new(&args.sonarr_url, &args.sonarr_api_key, &sonarr_options)
+            .context("Invalid connection parameters for Sonarr")?;
     sonarr_client
         .probe()
         .await
         .context("Probing Sonarr failed")?;
+    check_health(&sonarr_client).await;
+    if let Some(profile_id) = args.sonarr_quality_profile_id {
+        check_quality_profile(&sonarr_client, profile_id).await;
+    }
+
+    if args.webhook_port.is_some() && !args.media_server_type.supports_webhooks() {
+        warn!("Webhooks are only supported for Jellyfin and Plex, polling sessions instead");
+    }
 
-    let watcher: Pin<Box<dyn Future<Output = ()> + Send>> = match args.media_server_type {
+    let radarr_client = match (&args.radarr_url, &args.radarr_api_key) {
+        (Some(url), Some(api_key)) => {
+            let client = radarr::Client::new(url, api_key, &radarr_options(&args))
+                .context("Invalid connection parameters for Radarr")?;
+            client.probe().await.context("Probing Radarr failed")?;
+            Some(client)
+        }
+        _ => None,
+    };
+
+    let overseerr_client = match (&args.overseerr_url, &args.overseerr_api_key) {
+        (Some(url), Some(api_key)) => {
+            let client = overseerr::Client::new(url, api_key, &overseerr_options(&args))
+                .context("Invalid connection parameters for Overseerr")?;
+            client.probe().await.context("Probing Overseerr failed")?;
+            Some(client)
+        }
+        _ => None,
+    };
+
+    let media_server_options = media_server_options(&args);
+    let webhook_options = webhook_options(&args);
+    let watcher: Watcher = match args.media_server_type {
+        #[cfg(feature = "emby")]
         MediaServer::Jellyfin => {
-            info!("Start watching Jellyfin sessions");
-            let client = embyfin::Client::new(
-                &args.media_server_url,
-                &media_server_api_key,
-                embyfin::Fork::Jellyfin,
-            )
-            .context("Invalid connection parameters for Jellyfin")?;
-            client.probe().await.context("Probing Jellyfin failed")?;
-            Box::pin(client.watch(Duration::from_secs(args.interval), tx))
+            if let Some(webhook_options) = webhook_options {
+                info!("Start receiving Jellyfin webhooks");
+                Box::pin(async move { webhook::listen(&webhook_options, tx).await })
+            } else {
+                info!("Start watching Jellyfin sessions");
+                let client = embyfin::Client::new(
+                    &media_server_url,
+                    &media_server_api_key,
+                    embyfin::Fork::Jellyfin,
+                )
+                .context("Invalid connection parameters for Jellyfin")?;
+                let client = client
+                    .with_options(&media_server_options)
+                    .context("Invalid connection settings for Jellyfin")?;
+                client.probe().await.context("Probing Jellyfin failed")?;
+                let client = if args.include_next_up {
+                    client.with_next_up()
+                } else {
+                    client
+                };
+                if args.once {
+                    polling(client.watch_once(tx))
+                } else {
+                    polling(client.watch(Duration::from_secs(args.interval), tx))
+                }
+            }
         }
+        #[cfg(feature = "emby")]
         MediaServer::Emby => {
             info!("Start watching Emby sessions");
             let client = embyfin::Client::new(
-                &args.media_server_url,
+                &media_server_url,
                 &media_server_api_key,
                 embyfin::Fork::Emby,
             )
             .context("Invalid connection parameters for Emby")?;
+            let client = client
+                .with_options(&media_server_options)
+                .context("Invalid connection settings for Emby")?;
             client.probe().await.context("Probing Emby failed")?;
-            Box::pin(client.watch(Duration::from_secs(args.interval), tx))
+            let client = if args.include_next_up {
+                client.with_next_up()
+            } else {
+                client
+            };
+            if args.once {
+                polling(client.watch_once(tx))
+            } else {
+                polling(client.watch(Duration::from_secs(args.interval), tx))
+            }
         }
+        #[cfg(feature = "plex")]
         MediaServer::Plex => {
-            info!("Start watching Plex sessions");
-            let client = plex::Client::new(&args.media_server_url, &media_server_api_key)
+            let client = plex::Client::new(&media_server_url, &media_server_api_key)
                 .context("Invalid connection parameters for Plex")?;
+            let client = client
+                .with_options(&media_server_options)
+                .context("Invalid connection settings for Plex")?;
             client.probe().await.context("Probing Plex failed")?;
-            Box::pin(client.watch(Duration::from_secs(args.interval), tx))
+            if let Some(webhook_options) = webhook_options {
+                info!("Start receiving Plex webhooks");
+                Box::pin(async move { plex::webhook::listen(&webhook_options, client, tx).await })
+            } else if args.plex_on_deck {
+                info!("Start watching Plex On Deck");
+                let client = plex::OnDeckClient::new(client);
+                if args.once {
+                    polling(client.watch_once(tx))
+                } else {
+                    polling(client.watch(Duration::from_secs(args.interval), tx))
+                }
+            } else if args.once {
+                polling(client.watch_once(tx))
+            } else {
+                info!("Start watching Plex sessions");
+                polling(client.watch(Duration::from_secs(args.interval), tx))
+            }
+        }
+        MediaServer::Mock => {
+            info!("Start replaying mock sessions");
+            let sessions_file = args
+                .mock_sessions_file
+                .as_ref()
+                .expect("using value enforced via clap");
+            let client = mock::Client::new(sessions_file).context("Invalid mock sessions file")?;
+            if args.once {
+                polling(client.watch_once(tx))
+            } else {
+                polling(client.watch(Duration::from_secs(args.interval), tx))
+            }
+        }
+    };
+
+    if let Some(schedule) = args.scheduled_full_search_cron {
+        tokio::spawn(full_search(sonarr_client.clone(), schedule));
+    }
+
+    let seen_retention = Duration::from_secs(args.seen_retention_hours.saturating_mul(60 * 60));
+    let seen = match &args.state_dir {
+        Some(state_dir) => {
+            std::fs::create_dir_all(state_dir).context("Cannot create state directory")?;
+            Seen::load(&state_dir.join("seen.json"), seen_retention)
+                .context("Cannot load seen items")?
+        }
+        None => Seen::new(seen_retention),
+    };
+    let seen = if args.dry_run {
+        seen.without_saving()
+    } else {
+        seen
+    };
+    let actor = process::Actor::new(rx, sonarr_client, seen, args.remaining_episodes, args.users);
+    let actor = actor
+        .with_excluded_users(args.exclude_users)
+        .with_min_progress(args.min_progress)
+        .with_required_tags(args.sonarr_required_tags)
+        .with_required_quality_profile_ids(args.sonarr_required_quality_profile_ids)
+        .with_series(args.series)
+        .with_series_filter(filter::SeriesFilter::new(args.series_patterns));
+    let actor = match args.sonarr_max_concurrent_searches {
+        Some(max) => actor.with_max_concurrent_searches(max.get()),
+        None => actor,
+    };
+    let actor = if args.rescan_after_search {
+        actor.with_rescan_after_search()
+    } else {
+        actor
+    };
+    let actor = if args.rename_after_download {
+        actor.with_rename_after_download()
+    } else {
+        actor
+    };
+    let actor = match args.session_log {
+        Some(path) => actor.with_session_log(session_log::SessionLog::new(path)),
+        None => actor,
+    };
+    let actor = match args.sonarr_quality_profile_id {
+        Some(profile_id) => actor.with_quality_profile(profile_id),
+        None => actor,
+    };
+    let actor = match radarr_client {
+        Some(radarr_client) => actor.with_radarr(radarr_client),
+        None => actor,
+    };
+    let actor = match overseerr_client {
+        Some(overseerr_client) => actor.with_overseerr(overseerr_client),
+        None => actor,
+    };
+    // a single poll is processed to the end, that is the whole point of it
+    let actor = if args.once {
+        actor
+    } else {
+        actor.with_shutdown_timeout(SHUTDOWN_TIMEOUT)
+    };
+    let actor = if args.notification_url.is_empty() {
+        actor
+    } else {
+        let notifier = notify::Notifier::new(&args.notification_url, &args.notification_header)
+            .context("Invalid notification settings")?;
+        actor.with_notifier(notifier)
+    };
+
+    if args.once {
+        // the watcher shuts the actor down after a single poll
+        let (result, ()) = tokio::join!(watcher, actor.process());
+        result?;
+        info!("{NAME} exits");
+    } else {
+        tokio::select! {
+            result = watcher => result?,
+            () = actor.process() => info!("{NAME} exits"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Source of sessions, only failing if sessions can't be received at all
+type Watcher = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// Polling keeps going when the media server is unavailable, so it never fails
+fn polling(watch: impl Future<Output = ()> + Send + 'static) -> Watcher {
+    Box::pin(async move {
+        watch.await;
+        Ok(())
+    })
+}
+
+/// Find configuration problems without contacting any server
+fn check(args: &Args) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    // only left out for the mock, see Args::require_media_server
+    let media_server_api_key = args
+        .media_server_api_key
+        .as_deref()
+        .or(args.jellyfin_api_key.as_deref())
+        .unwrap_or_default();
+    let media_server_url = args.media_server_url.as_deref().unwrap_or_default();
+    let media_server_options = media_server_options(args);
+    let media_server = match args.media_server_type {
+        #[cfg(feature = "emby")]
+        MediaServer::Jellyfin => embyfin::Client::new(
+            media_server_url,
+            media_server_api_key,
+            embyfin::Fork::Jellyfin,
+        )
+        .and_then(|client| client.with_options(&media_server_options))
+        .map(drop),
+        #[cfg(feature = "emby")]
+        MediaServer::Emby => {
+            embyfin::Client::new(media_server_url, media_server_api_key, embyfin::Fork::Emby)
+                .and_then(|client| client.with_options(&media_server_options))
+                .map(drop)
         }
+        #[cfg(feature = "plex")]
+        MediaServer::Plex => plex::Client::new(media_server_url, media_server_api_key)
+            .and_then(|client| client.with_options(&media_server_options))
+            .map(drop),
+        MediaServer::Mock => args
+            .mock_sessions_file
+            .as_ref()
+            .map_or(Ok(()), |path| mock::Client::new(path).map(drop)),
     };
+    if let Err(e) = media_server {
+        problems.push(format!("Invalid media server configuration: {e:#}"));
+    }
+
+    let sonarr_options = sonarr_options(args);
+    if let Err(e) = sonarr::Client::new(&args.sonarr_url, &args.sonarr_api_key, &sonarr_options) {
+        problems.push(format!("Invalid connection parameters for Sonarr: {e:#}"));
+    }
+
+    if let (Some(url), Some(api_key)) = (&args.radarr_url, &args.radarr_api_key) {
+        if let Err(e) = radarr::Client::new(url, api_key, &radarr_options(args)) {
+            problems.push(format!("Invalid connection parameters for Radarr: {e:#}"));
+        }
+    }
+
+    if let (Some(url), Some(api_key)) = (&args.overseerr_url, &args.overseerr_api_key) {
+        if let Err(e) = overseerr::Client::new(url, api_key, &overseerr_options(args)) {
+            problems.push(format!(
+                "Invalid connection parameters for Overseerr: {e:#}"
+            ));
+        }
+    }
+
+    if args.remaining_episodes == 0 {
+        problems.push(
+            "`--remaining-episodes 0` only triggers searches for stand-alone pilots".to_string(),
+        );
+    }
 
-    let seen = Seen::default();
-    let mut actor =
-        process::Actor::new(rx, sonarr_client, seen, args.remaining_episodes, args.users);
+    if args.interval == 0 {
+        problems.push("`--interval 0` polls the media server without pause".to_string());
+    }
+
+    let mut users = std::collections::HashSet::new();
+    for user in &args.users {
+        if !users.insert(user.trim().to_lowercase()) {
+            problems.push(format!("User `{user}` is listed more than once"));
+        }
+    }
 
-    tokio::join!(watcher, actor.process());
+    problems
+}
 
+async fn push_release(args: &Args, guid: &str) -> anyhow::Result<()> {
+    let (Some(indexer_id), Some(episode_id)) =
+        (args.push_release_indexer_id, args.push_release_episode_id)
+    else {
+        anyhow::bail!("pushing a release requires an indexer and an episode");
+    };
+    let sonarr_options = sonarr_options(args);
+    let mut sonarr_client =
+        sonarr::Client::new(&args.sonarr_url, &args.sonarr_api_key, &sonarr_options)
+            .context("Invalid connection parameters for Sonarr")?;
+    sonarr_client
+        .probe()
+        .await
+        .context("Probing Sonarr failed")?;
+    sonarr_client
+        .release_push(guid, indexer_id, episode_id)
+        .await
+        .context("Pushing the release failed")?;
     Ok(())
 }
 
+/// Wait for Ctrl+C, or SIGTERM as sent by `docker stop`
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+async fn check_quality_profile(sonarr_client: &sonarr::Client, profile_id: i32) {
+    match sonarr_client.quality_profiles().await {
+        Ok(profiles) if !profiles.iter().any(|p| p.id == profile_id) => {
+            let valid = profiles
+                .iter()
+                .map(|p| format!("{} ({})", p.id, p.name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            warn!("Quality profile {profile_id} does not exist in Sonarr. Valid profiles: {valid}");
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Cannot verify quality profile {profile_id}: {e:#}"),
+    }
+}
+
+async fn check_health(sonarr_client: &sonarr::Client) {
+    match sonarr_client.health().await {
+        Ok(health) => {
+            for check in health.iter().filter(|h| h.is_problem()) {
+                warn!("Sonarr health {}: {}", check.r#type, check.message);
+            }
+        }
+        Err(e) => debug!("Cannot get the health of Sonarr: {e:#}"),
+    }
+}
+
+async fn full_search(sonarr_client: sonarr::Client, schedule: cron::Schedule) {
+    for next in schedule.upcoming(chrono::Utc) {
+        let delay = (next - chrono::Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(delay).await;
+
+        info!("Searching all missing episodes");
+        if let Err(e) = sonarr_client.search_all_missing().await {
+            error!("Scheduled search failed: {e:#}");
+        }
+    }
+}
+
+fn parse_cron(expr: &str) -> Result<cron::Schedule, String> {
+    // `cron` wants a seconds field, also accept the common five field format
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {expr}")
+    } else {
+        expr.to_string()
+    };
+    expr.parse().map_err(|e: cron::error::Error| e.to_string())
+}
+
+fn parse_percent(percent: &str) -> Result<f32, String> {
+    let percent: f32 = percent
+        .parse()
+        .map_err(|e| format!("invalid percentage `{percent}`: {e}"))?;
+    if (0.0..=100.0).contains(&percent) {
+        Ok(percent)
+    } else {
+        Err(format!("{percent} is not between 0 and 100"))
+    }
+}
+
+fn parse_cookie(cookie: &str) -> Result<(String, String), String> {
+    let (name, value) = cookie
+        .split_once('=')
+        .ok_or_else(|| format!("no `=` found in `{cookie}`"))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("no `:` found in `{header}`"))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
 fn enable_logging(log_dir: &Option<PathBuf>) {
     let env_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::INFO.into())
@@ -181,6 +1042,145 @@ fn enable_logging(log_dir: &Option<PathBuf>) {
         .expect("setting the default subscriber");
 }
 
+/// Summarize the settings in effect, leaving out API keys and other secrets
+fn log_settings(args: &Args) {
+    info!(
+        media_server_type = ?args.media_server_type,
+        media_server_url = args.media_server_url.as_deref().map(redact_url),
+        sonarr_url = redact_url(&args.sonarr_url),
+        radarr_url = args.radarr_url.as_deref().map(redact_url),
+        overseerr_url = args.overseerr_url.as_deref().map(redact_url),
+        interval_secs = args.interval,
+        remaining_episodes = args.remaining_episodes,
+        webhook_port = ?args.webhook_port,
+        webhook_address = %args.webhook_address,
+        metrics_port = ?args.metrics_port,
+        state_dir = ?args.state_dir,
+        seen_retention_hours = args.seen_retention_hours,
+        log_dir = ?args.log_dir,
+        session_log = ?args.session_log,
+        mock_sessions_file = ?args.mock_sessions_file,
+        scheduled_full_search = args
+            .scheduled_full_search_cron
+            .as_ref()
+            .map(ToString::to_string),
+        notification_urls = args.notification_url.len(),
+        dry_run = args.dry_run,
+        once = args.once,
+        "Settings"
+    );
+    info!(
+        users = args.users.len(),
+        excluded_users = args.exclude_users.len(),
+        series = args.series.len(),
+        series_patterns = ?args.series_patterns.iter().map(regex::Regex::as_str).collect::<Vec<_>>(),
+        required_tags = ?args.sonarr_required_tags,
+        required_quality_profile_ids = ?args.sonarr_required_quality_profile_ids,
+        quality_profile_id = ?args.sonarr_quality_profile_id,
+        min_progress = args.min_progress,
+        rescan_after_search = args.rescan_after_search,
+        rename_after_download = args.rename_after_download,
+        "Series settings"
+    );
+    info!(
+        sonarr_search_timeout_secs = args.sonarr_search_timeout,
+        sonarr_max_concurrent_searches = ?args.sonarr_max_concurrent_searches,
+        sonarr_rate_limit = ?args.sonarr_rate_limit,
+        sonarr_request_retries = args.sonarr_request_retries,
+        sonarr_retry_delay_secs = args.sonarr_retry_delay,
+        sonarr_max_retry_delay_secs = ?args.sonarr_max_retry_delay,
+        sonarr_max_idle_connections = ?args.sonarr_max_idle_connections,
+        sonarr_cookies = ?args.sonarr_cookies.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+        sonarr_ca_cert = ?args.sonarr_ca_cert,
+        sonarr_client_cert = ?args.sonarr_client_cert,
+        sonarr_request_timeout_secs = args.sonarr_request_timeout,
+        sonarr_connect_timeout_secs = args.sonarr_connect_timeout,
+        radarr_ca_cert = ?args.radarr_ca_cert,
+        radarr_request_timeout_secs = args.radarr_request_timeout,
+        radarr_connect_timeout_secs = args.radarr_connect_timeout,
+        overseerr_ca_cert = ?args.overseerr_ca_cert,
+        overseerr_request_timeout_secs = args.overseerr_request_timeout,
+        overseerr_connect_timeout_secs = args.overseerr_connect_timeout,
+        media_server_ca_cert = ?args.media_server_ca_cert,
+        media_server_request_timeout_secs = args.media_server_request_timeout,
+        media_server_connect_timeout_secs = args.media_server_connect_timeout,
+        "Connection settings"
+    );
+    #[cfg(feature = "emby")]
+    info!(
+        include_next_up = args.include_next_up,
+        "Jellyfin/Emby settings"
+    );
+    #[cfg(feature = "plex")]
+    info!(plex_on_deck = args.plex_on_deck, "Plex settings");
+}
+
+fn sonarr_options(args: &Args) -> sonarr::Options {
+    sonarr::Options {
+        cookies: args.sonarr_cookies.iter().cloned().collect(),
+        dry_run: args.dry_run,
+        search_timeout: Duration::from_secs(args.sonarr_search_timeout),
+        rate_limit: args.sonarr_rate_limit,
+        request_retries: args.sonarr_request_retries,
+        retry_delay: Duration::from_secs(args.sonarr_retry_delay),
+        max_retry_delay: args.sonarr_max_retry_delay.map(Duration::from_secs),
+        ca_cert: args.sonarr_ca_cert.clone(),
+        client_cert: args
+            .sonarr_client_cert
+            .clone()
+            .zip(args.sonarr_client_key.clone()),
+        max_idle_connections: args.sonarr_max_idle_connections,
+        request_timeout: Some(Duration::from_secs(args.sonarr_request_timeout)),
+        connect_timeout: Some(Duration::from_secs(args.sonarr_connect_timeout)),
+    }
+}
+
+fn radarr_options(args: &Args) -> radarr::Options {
+    radarr::Options {
+        dry_run: args.dry_run,
+        ca_cert: args.radarr_ca_cert.clone(),
+        request_timeout: Some(Duration::from_secs(args.radarr_request_timeout)),
+        connect_timeout: Some(Duration::from_secs(args.radarr_connect_timeout)),
+    }
+}
+
+fn overseerr_options(args: &Args) -> overseerr::Options {
+    overseerr::Options {
+        dry_run: args.dry_run,
+        ca_cert: args.overseerr_ca_cert.clone(),
+        request_timeout: Some(Duration::from_secs(args.overseerr_request_timeout)),
+        connect_timeout: Some(Duration::from_secs(args.overseerr_connect_timeout)),
+    }
+}
+
+fn media_server_options(args: &Args) -> media_server::Options {
+    media_server::Options {
+        ca_cert: args.media_server_ca_cert.clone(),
+        request_timeout: Some(Duration::from_secs(args.media_server_request_timeout)),
+        connect_timeout: Some(Duration::from_secs(args.media_server_connect_timeout)),
+    }
+}
+
+fn webhook_options(args: &Args) -> Option<media_server::WebhookOptions> {
+    let port = args.webhook_port?;
+    Some(media_server::WebhookOptions {
+        address: args.webhook_address,
+        port,
+        token: args.webhook_token.clone()?,
+    })
+}
+
+/// Hide a password that may be part of the URL
+fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("***"));
+            url.to_string()
+        }
+        _ => url.to_string(),
+    }
+}
+
 fn warn_deprecated(args: &Args) {
     if std::env::args().any(|arg| arg == "--jellyfin-url") {
         warn!("`--jellyfin-url` is deprecated. Use `--media-server-url` instead.");
@@ -1,26 +1,174 @@
-use std::time::Duration;
+use std::{net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use tokio::sync::mpsc;
+use anyhow::{Context as _, Result};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse as _, Response},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::mpsc};
 use tracing::{debug, error};
 
 use crate::Message;
 
+#[cfg(feature = "emby")]
 pub mod embyfin;
+pub mod mock;
+#[cfg(feature = "plex")]
 pub mod plex;
+#[cfg(feature = "emby")]
+pub mod webhook;
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// Connection settings shared by all media server clients
+#[derive(Default)]
+pub struct Options {
+    /// Trust only the certificate authorities in this PEM file
+    pub ca_cert: Option<PathBuf>,
+    /// Give up on requests that take longer than this
+    pub request_timeout: Option<Duration>,
+    /// Give up on connecting after this long
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Options {
+    fn client(&self, headers: HeaderMap) -> Result<reqwest::Client> {
+        let builder = crate::tls::client_builder(
+            headers,
+            self.ca_cert.as_deref(),
+            self.request_timeout,
+            self.connect_timeout,
+        )?;
+        Ok(builder.build()?)
+    }
+}
+
+/// Where to receive webhooks, and the secret they have to carry
+pub struct WebhookOptions {
+    pub address: IpAddr,
+    pub port: u16,
+    /// Expected as `token` query parameter or `X-Prefetcharr-Token` header
+    pub token: String,
+}
+
+impl WebhookOptions {
+    async fn bind(&self) -> Result<TcpListener> {
+        TcpListener::bind((self.address, self.port))
+            .await
+            .with_context(|| {
+                format!(
+                    "Cannot listen for webhooks on {}:{}",
+                    self.address, self.port
+                )
+            })
+    }
+}
+
+/// Reject webhooks without the secret `token` before their body is parsed
+fn require_token<S>(router: Router<S>, token: &str) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.route_layer(middleware::from_fn_with_state(
+        Arc::<str>::from(token),
+        check_token,
+    ))
+}
+
+async fn check_token(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let from_query = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("token="));
+    let from_header = request
+        .headers()
+        .get("X-Prefetcharr-Token")
+        .and_then(|value| value.to_str().ok());
+    if [from_query, from_header].contains(&Some(&*token)) {
+        next.run(request).await
+    } else {
+        debug!("Rejecting webhook without a valid token");
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum Series {
     Title(String),
     Tvdb(i32),
+    Imdb(String),
+    Tmdb(i32),
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// Metadata providers to identify a series by, most preferred first
+const SERIES_PROVIDERS: [&str; 3] = ["tvdb", "imdb", "tmdb"];
+/// Metadata providers to identify a movie by, most preferred first
+const MOVIE_PROVIDERS: [&str; 2] = ["tmdb", "imdb"];
+
+impl Series {
+    /// Pick the ID of the most preferred provider out of `(provider, id)`
+    /// pairs. Provider names are matched case-insensitively and IDs that
+    /// don't parse are skipped.
+    fn from_provider_ids(ids: &[(&str, &str)], preference: &[&str]) -> Option<Self> {
+        preference.iter().find_map(|wanted| {
+            ids.iter()
+                .filter(|(provider, id)| provider.eq_ignore_ascii_case(wanted) && !id.is_empty())
+                .find_map(|&(_, id)| match *wanted {
+                    "tvdb" => id.parse().ok().map(Self::Tvdb),
+                    "imdb" => Some(Self::Imdb(id.to_string())),
+                    "tmdb" => id.parse().ok().map(Self::Tmdb),
+                    _ => None,
+                })
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MediaKind {
+    #[default]
+    Episode,
+    Movie,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct NowPlaying {
+    /// The series, or the movie itself for [`MediaKind::Movie`]
     pub series: Series,
     pub episode: i32,
     pub season: i32,
     pub user_id: String,
     pub user_name: String,
+    #[serde(default)]
+    pub is_paused: bool,
+    pub position_ticks: Option<i64>,
+    /// How much of the item was played, from 0 to 100
+    #[serde(default)]
+    pub progress_pct: Option<f32>,
+    /// When the server last saw the item playing
+    #[serde(skip)]
+    pub played_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub kind: MediaKind,
+}
+
+/// Percentage of `duration` reached at `position`, both given in the same unit
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+pub fn progress_pct(position: i64, duration: i64) -> Option<f32> {
+    (duration > 0).then(|| (position as f64 * 100.0 / duration as f64) as f32)
+}
+
+/// Parse a timestamp as reported by a media server
+#[cfg_attr(not(feature = "emby"), allow(dead_code))]
+pub fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
 }
 
 pub trait MediaServer: Sized {
@@ -30,22 +178,35 @@ pub trait MediaServer: Sized {
     async fn extract(&self, session: Self::Session) -> Result<NowPlaying, Self::Error>;
     async fn watch(self, interval: Duration, tx: mpsc::Sender<Message>) {
         loop {
-            match self.sessions().await {
-                Ok(sessions) => {
-                    for session in sessions {
-                        match self.extract(session).await {
-                            Ok(now_playing) => {
-                                tx.send(Message::NowPlaying(now_playing))
-                                    .await
-                                    .expect("sending to event loop");
-                            }
-                            Err(e) => debug!("Ignoring session: {e}"),
+            self.poll(&tx).await;
+            tokio::time::sleep(interval).await;
+        }
+    }
+    /// Forward the current sessions a single time, then shut down
+    async fn watch_once(self, tx: mpsc::Sender<Message>) {
+        self.poll(&tx).await;
+        tx.send(Message::Shutdown)
+            .await
+            .expect("sending to event loop");
+    }
+    async fn poll(&self, tx: &mpsc::Sender<Message>) {
+        match self.sessions().await {
+            Ok(sessions) => {
+                for session in sessions {
+                    match self.extract(session).await {
+                        Ok(now_playing) => {
+                            tx.send(Message::NowPlaying(now_playing))
+                                .await
+                                .expect("sending to event loop");
                         }
+                        Err(e) => debug!("Ignoring session: {e}"),
                     }
                 }
-                Err(err) => error!("cannot fetch sessions from media server: {err}"),
             }
-            tokio::time::sleep(interval).await;
+            Err(err) => {
+                metrics::counter!(crate::prometheus::POLL_ERRORS).increment(1);
+                error!("cannot fetch sessions from media server: {err}");
+            }
         }
     }
 }
@@ -7,8 +7,9 @@ use reqwest::{
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
+use tracing::{debug, instrument, Span};
 
-use super::{MediaServer, NowPlaying};
+use super::{MediaKind, MediaServer, NowPlaying};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -16,10 +17,44 @@ struct Episode {
     series_id: String,
     season_id: String,
     index_number: i32,
+    run_time_ticks: Option<i64>,
     #[serde(flatten)]
     _other: serde_json::Value,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum MovieType {
+    Movie,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Movie {
+    name: String,
+    r#type: MovieType,
+    run_time_ticks: Option<i64>,
+    #[serde(default)]
+    provider_ids: HashMap<String, String>,
+    #[serde(flatten)]
+    _other: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Item {
+    Episode(Episode),
+    Movie(Movie),
+}
+
+impl Item {
+    fn run_time_ticks(&self) -> Option<i64> {
+        match self {
+            Item::Episode(episode) => episode.run_time_ticks,
+            Item::Movie(movie) => movie.run_time_ticks,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct Season {
@@ -37,16 +72,66 @@ struct Series {
     _other: serde_json::Value,
 }
 
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PlayState {
+    position_ticks: Option<i64>,
+    #[serde(default)]
+    is_paused: bool,
+    play_method: Option<String>,
+    #[serde(flatten)]
+    _other: serde_json::Value,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct SessionInfo {
     user_id: String,
     user_name: String,
-    now_playing_item: Episode,
+    now_playing_item: Item,
+    #[serde(default)]
+    play_state: PlayState,
+    last_activity_date: Option<String>,
     #[serde(flatten)]
     other: serde_json::Value,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct User {
+    id: String,
+    name: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ItemList {
+    #[serde(default)]
+    items: Vec<Value>,
+}
+
+impl ItemList {
+    fn episodes(self) -> impl Iterator<Item = Episode> {
+        self.items
+            .into_iter()
+            .filter_map(|item| serde_json::from_value(item).ok())
+    }
+}
+
+/// A list that is either complete or a single page of a longer one
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Paged<T> {
+    #[serde(rename_all = "PascalCase")]
+    Page {
+        items: Vec<T>,
+        total_record_count: usize,
+    },
+    List(Vec<T>),
+}
+
+const PAGE_SIZE: usize = 100;
+
 #[derive(Clone, Copy)]
 pub enum Fork {
     Jellyfin,
@@ -56,6 +141,8 @@ pub enum Fork {
 pub struct Client {
     base_url: Url,
     client: reqwest::Client,
+    headers: HeaderMap,
+    include_next_up: bool,
 }
 
 impl Client {
@@ -76,6 +163,9 @@ impl Client {
                 let mut token = HeaderValue::from_str(api_key)?;
                 token.set_sensitive(true);
 
+                // newer Emby versions expect the token under a different
+                // name, older ones only know the original header
+                headers.insert("X-MediaBrowser-Token", token.clone());
                 headers.insert("X-Emby-Token", token);
             }
         }
@@ -85,22 +175,113 @@ impl Client {
             HeaderValue::from_static("application/json"),
         );
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()?;
+        let client = crate::tls::client_builder(headers.clone(), None, None, None)?.build()?;
 
-        Ok(Self { base_url, client })
+        Ok(Self {
+            base_url,
+            client,
+            headers,
+            include_next_up: false,
+        })
+    }
+
+    /// Apply certificate and timeout settings to all further requests
+    pub fn with_options(mut self, options: &super::Options) -> Result<Self> {
+        self.client = options.client(self.headers.clone())?;
+        Ok(self)
+    }
+
+    /// Also report the "Next Up" and "Continue Watching" episodes of every
+    /// user as sessions
+    pub fn with_next_up(mut self) -> Self {
+        self.include_next_up = true;
+        self
     }
 
     async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.get_query(path, &[]).await
+    }
+
+    async fn get_query<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T> {
         let mut url = self.base_url.clone();
         url.path_segments_mut()
             .map_err(|()| anyhow!("url is relative"))?
             .extend(path.split('/'));
-        let response = self.client.get(url).send().await?.error_for_status()?;
+        let response = self
+            .client
+            .get(url)
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?;
         Ok(response.json::<T>().await?)
     }
 
+    /// Fetch every page of a list that the server may split up
+    async fn get_paged<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        let limit = PAGE_SIZE.to_string();
+        let mut items = Vec::new();
+        loop {
+            let start_index = items.len().to_string();
+            let query = [
+                ("StartIndex", start_index.as_str()),
+                ("Limit", limit.as_str()),
+            ];
+            match self.get_query(path, &query).await? {
+                Paged::List(list) => {
+                    items.extend(list);
+                    return Ok(items);
+                }
+                Paged::Page {
+                    items: page,
+                    total_record_count,
+                } => {
+                    let is_empty = page.is_empty();
+                    items.extend(page);
+                    if is_empty || items.len() >= total_record_count {
+                        return Ok(items);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Episodes the user is expected to watch next
+    async fn next_up(&self, user_id: &str) -> Result<Vec<Episode>> {
+        let items: ItemList = self
+            .get_query("Shows/NextUp", &[("userId", user_id)])
+            .await?;
+        Ok(items.episodes().collect())
+    }
+
+    /// Episodes the user started but did not finish
+    async fn resume(&self, user_id: &str) -> Result<Vec<Episode>> {
+        let items: ItemList = self.get(&format!("Users/{user_id}/Items/Resume")).await?;
+        Ok(items.episodes().collect())
+    }
+
+    /// Sessions made up from the next up and resumable episodes of all users
+    async fn suggested_sessions(&self) -> Result<Vec<SessionInfo>> {
+        let mut sessions = Vec::new();
+        for user in self.get::<Vec<User>>("Users").await? {
+            let mut episodes = self.next_up(&user.id).await?;
+            episodes.extend(self.resume(&user.id).await?);
+            sessions.extend(episodes.into_iter().map(|episode| SessionInfo {
+                user_id: user.id.clone(),
+                user_name: user.name.clone(),
+                now_playing_item: Item::Episode(episode),
+                play_state: PlayState::default(),
+                last_activity_date: None,
+                other: Value::Null,
+            }));
+        }
+        Ok(sessions)
+    }
+
     async fn item<T: DeserializeOwned>(&self, user_id: &str, item_id: &str) -> Result<T> {
         let path = format!("Users/{user_id}/Items/{item_id}");
         self.get(path.as_str()).await
@@ -119,23 +300,12 @@ struct Ids {
     season: String,
 }
 
-impl From<SessionInfo> for Ids {
-    fn from(session: SessionInfo) -> Self {
-        Ids::new(&session)
-    }
-}
-
 impl Ids {
-    fn new(session: &SessionInfo) -> Self {
-        let user_id = session.user_id.clone();
-        let np = &session.now_playing_item;
-        let series_id = np.series_id.clone();
-        let season_id = np.season_id.clone();
-
+    fn new(user_id: &str, episode: &Episode) -> Self {
         Self {
-            user: user_id,
-            series: series_id,
-            season: season_id,
+            user: user_id.to_string(),
+            series: episode.series_id.clone(),
+            season: episode.season_id.clone(),
         }
     }
 }
@@ -145,36 +315,86 @@ impl MediaServer for Client {
     type Error = anyhow::Error;
 
     async fn sessions(&self) -> std::prelude::v1::Result<Vec<Self::Session>, Self::Error> {
-        Ok(self
-            .get::<Vec<Value>>("Sessions")
+        let mut sessions = self
+            .get_paged::<Value>("Sessions")
             .await?
-            .iter()
-            .cloned()
+            .into_iter()
             .map(serde_json::from_value)
             .filter_map(Result::ok)
-            .collect::<Vec<Self::Session>>())
+            .collect::<Vec<Self::Session>>();
+        if self.include_next_up {
+            match self.suggested_sessions().await {
+                Ok(suggested) => sessions.extend(suggested),
+                Err(e) => debug!("cannot fetch next up episodes: {e}"),
+            }
+        }
+        Ok(sessions)
     }
 
+    #[instrument(
+        name = "session",
+        skip_all,
+        fields(user = %session.user_name, series_id = tracing::field::Empty)
+    )]
     async fn extract(
         &self,
         session: Self::Session,
     ) -> std::prelude::v1::Result<NowPlaying, Self::Error> {
-        let episode_num = session.now_playing_item.index_number;
-        let user_id = session.user_id.clone();
-        let user_name = session.user_name.clone();
-        let ids = Ids::from(session);
-
-        let series: Series = self.item(&ids.user, &ids.series).await?;
+        let user_id = session.user_id;
+        let user_name = session.user_name;
+        let is_paused = session.play_state.is_paused;
+        let position_ticks = session.play_state.position_ticks;
+        let progress_pct = position_ticks
+            .zip(session.now_playing_item.run_time_ticks())
+            .and_then(|(position, run_time)| super::progress_pct(position, run_time));
+        let played_at = session
+            .last_activity_date
+            .as_deref()
+            .and_then(super::parse_time);
+
+        let episode = match session.now_playing_item {
+            Item::Episode(episode) => episode,
+            Item::Movie(movie) => {
+                let provider_ids = movie
+                    .provider_ids
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect::<Vec<_>>();
+                return Ok(NowPlaying {
+                    series: super::Series::from_provider_ids(
+                        &provider_ids,
+                        &super::MOVIE_PROVIDERS,
+                    )
+                    .unwrap_or(super::Series::Title(movie.name)),
+                    episode: 0,
+                    season: 0,
+                    user_id,
+                    user_name,
+                    is_paused,
+                    position_ticks,
+                    progress_pct,
+                    played_at,
+                    kind: MediaKind::Movie,
+                });
+            }
+        };
+        Span::current().record("series_id", episode.series_id.as_str());
+        let episode_num = episode.index_number;
+        let ids = Ids::new(&user_id, &episode);
 
-        let season: Season = self.item(&ids.user, &ids.season).await?;
+        let (series, season): (Series, Season) = tokio::try_join!(
+            self.item(&ids.user, &ids.series),
+            self.item(&ids.user, &ids.season)
+        )?;
         let season_num = season.index_number;
 
-        let tvdb_id = series.provider_ids.get("Tvdb");
-
-        let series = match tvdb_id {
-            Some(tvdb) => super::Series::Tvdb(tvdb.parse()?),
-            None => super::Series::Title(series.name),
-        };
+        let provider_ids = series
+            .provider_ids
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        let series = super::Series::from_provider_ids(&provider_ids, &super::SERIES_PROVIDERS)
+            .unwrap_or(super::Series::Title(series.name));
 
         let now_playing = NowPlaying {
             series,
@@ -182,6 +402,11 @@ impl MediaServer for Client {
             season: season_num,
             user_id,
             user_name,
+            is_paused,
+            position_ticks,
+            progress_pct,
+            played_at,
+            kind: MediaKind::Episode,
         };
 
         Ok(now_playing)
@@ -195,7 +420,7 @@ mod test {
     use tokio::sync::mpsc;
 
     use crate::{
-        media_server::{embyfin, MediaServer, NowPlaying, Series},
+        media_server::{embyfin, MediaKind, MediaServer, NowPlaying, Series},
         Message,
     };
 
@@ -259,11 +484,153 @@ mod test {
             season: 3,
             user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
             user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+        series_mock.assert_async().await;
+        season_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookup() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions");
+                then.json_body(episode());
+            })
+            .await;
+
+        let season_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/b");
+                then.delay(Duration::from_millis(500))
+                    .json_body(serde_json::json!({"IndexNumber": 3}));
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/a");
+                then.delay(Duration::from_millis(500)).json_body(series());
+            })
+            .await;
+
+        let client = embyfin::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            embyfin::Fork::Jellyfin,
+        )?;
+
+        let session = client.sessions().await?.remove(0);
+        let start = Instant::now();
+        let now_playing = client.extract(session).await?;
+        assert!(Instant::now().duration_since(start) < Duration::from_millis(1000));
+        assert_eq!(now_playing.series, Series::Tvdb(1234));
+        assert_eq!(now_playing.season, 3);
+
+        sessions_mock.assert_async().await;
+        series_mock.assert_async().await;
+        season_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn next_up() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions");
+                then.json_body(serde_json::json!([]));
+            })
+            .await;
+
+        let users_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users");
+                then.json_body(serde_json::json!([{ "Id": "u1", "Name": "user" }]));
+            })
+            .await;
+
+        let next_up_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Shows/NextUp")
+                    .query_param("userId", "u1");
+                then.json_body(serde_json::json!({
+                    "Items": [{
+                        "SeriesId": "a",
+                        "SeasonId": "b",
+                        "IndexNumber": 5
+                    }]
+                }));
+            })
+            .await;
+
+        let resume_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/u1/Items/Resume");
+                then.json_body(serde_json::json!({
+                    "Items": [{ "Name": "Test Movie", "Type": "Movie" }]
+                }));
+            })
+            .await;
+
+        let season_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/u1/Items/b");
+                then.json_body(serde_json::json!({"IndexNumber": 3}));
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/u1/Items/a");
+                then.json_body(series());
+            })
+            .await;
+
+        let client = embyfin::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            embyfin::Fork::Jellyfin,
+        )?
+        .with_next_up();
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 5,
+            season: 3,
+            user_id: "u1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         });
 
         assert_eq!(message, Some(message_expect));
 
         sessions_mock.assert_async().await;
+        users_mock.assert_async().await;
+        next_up_mock.assert_async().await;
+        resume_mock.assert_async().await;
         series_mock.assert_async().await;
         season_mock.assert_async().await;
 
@@ -331,6 +698,11 @@ mod test {
             season: 3,
             user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
             user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         });
 
         assert_eq!(message, Some(message_expect));
@@ -383,6 +755,125 @@ mod test {
             season: 3,
             user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
             user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+        series_mock.assert_async().await;
+        season_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn provider_id_case() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions");
+                then.json_body(episode());
+            })
+            .await;
+
+        let season_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/b");
+                then.json_body(serde_json::json!({"IndexNumber": 3}));
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/a");
+                then.json_body(serde_json::json!({
+                    "Name": "Test Show",
+                    "ProviderIds": { "TVDB": "1234" }
+                }));
+            })
+            .await;
+
+        let client =
+            embyfin::Client::new(&server.url("/pathprefix"), "secret", embyfin::Fork::Emby)?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 5,
+            season: 3,
+            user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+        series_mock.assert_async().await;
+        season_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn imdb_provider_id() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions");
+                then.json_body(episode());
+            })
+            .await;
+
+        let season_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/b");
+                then.json_body(serde_json::json!({"IndexNumber": 3}));
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/a");
+                then.json_body(serde_json::json!({
+                    "Name": "Test Show",
+                    "ProviderIds": { "Imdb": "tt0123456", "Tmdb": "4321" }
+                }));
+            })
+            .await;
+
+        let client =
+            embyfin::Client::new(&server.url("/pathprefix"), "secret", embyfin::Fork::Emby)?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Imdb("tt0123456".to_string()),
+            episode: 5,
+            season: 3,
+            user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         });
 
         assert_eq!(message, Some(message_expect));
@@ -395,6 +886,191 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn movie() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions");
+                then.json_body(serde_json::json!(
+                    [{
+                        "UserId": "08ba1929-681e-4b24-929b-9245852f65c0",
+                        "UserName": "user",
+                        "NowPlayingItem": {
+                            "Name": "Test Movie",
+                            "Type": "Movie"
+                        }
+                    }]
+                ));
+            })
+            .await;
+
+        let client = embyfin::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            embyfin::Fork::Jellyfin,
+        )?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Title("Test Movie".to_string()),
+            episode: 0,
+            season: 0,
+            user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Movie,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn movie_provider_ids() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions");
+                then.json_body(serde_json::json!(
+                    [{
+                        "Id": "6a2f1c1d0e5b4c7f9b0a8e3d2c1b0a9f",
+                        "Client": "Jellyfin Web",
+                        "DeviceName": "Firefox",
+                        "UserId": "08ba1929-681e-4b24-929b-9245852f65c0",
+                        "UserName": "user",
+                        "PlayState": {
+                            "PositionTicks": 40_800_000_000_i64,
+                            "IsPaused": false
+                        },
+                        "NowPlayingItem": {
+                            "Name": "Test Movie",
+                            "Id": "f0e1d2c3b4a596870f1e2d3c4b5a6978",
+                            "Type": "Movie",
+                            "MediaType": "Video",
+                            "ProductionYear": 1999,
+                            "RunTimeTicks": 81_600_000_000_i64,
+                            "ProviderIds": {
+                                "Imdb": "tt0133093",
+                                "Tmdb": "603"
+                            }
+                        }
+                    }]
+                ));
+            })
+            .await;
+
+        let client = embyfin::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            embyfin::Fork::Jellyfin,
+        )?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tmdb(603),
+            episode: 0,
+            season: 0,
+            user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: Some(40_800_000_000),
+            progress_pct: Some(50.0),
+            played_at: None,
+            kind: MediaKind::Movie,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn play_state() -> Result<(), Box<dyn std::error::Error>> {
+        for is_paused in [false, true] {
+            let server = httpmock::MockServer::start_async().await;
+
+            let _sessions_mock = server
+                .mock_async(|when, then| {
+                    when.path("/pathprefix/Sessions");
+                    then.json_body(serde_json::json!(
+                        [{
+                            "UserId": "08ba1929-681e-4b24-929b-9245852f65c0",
+                            "UserName": "user",
+                            "NowPlayingItem": {
+                                "SeriesId": "a",
+                                "SeasonId": "b",
+                                "IndexNumber": 5
+                            },
+                            "PlayState": {
+                                "PositionTicks": 12_345_678,
+                                "IsPaused": is_paused,
+                                "PlayMethod": "DirectPlay"
+                            }
+                        }]
+                    ));
+                })
+                .await;
+
+            let _season_mock = server
+                .mock_async(|when, then| {
+                    when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/b");
+                    then.json_body(serde_json::json!({"IndexNumber": 3}));
+                })
+                .await;
+
+            let _series_mock = server
+                .mock_async(|when, then| {
+                    when.path("/pathprefix/Users/08ba1929-681e-4b24-929b-9245852f65c0/Items/a");
+                    then.json_body(series());
+                })
+                .await;
+
+            let client = embyfin::Client::new(
+                &server.url("/pathprefix"),
+                "secret",
+                embyfin::Fork::Jellyfin,
+            )?;
+
+            let (tx, mut rx) = mpsc::channel(1);
+            let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+            let message = rx.recv().await;
+            let message_expect = Message::NowPlaying(NowPlaying {
+                series: Series::Tvdb(1234),
+                episode: 5,
+                season: 3,
+                user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
+                user_name: "user".to_string(),
+                is_paused,
+                position_ticks: Some(12_345_678),
+                progress_pct: None,
+                played_at: None,
+                kind: MediaKind::Episode,
+            });
+
+            assert_eq!(message, Some(message_expect));
+
+            watcher.abort();
+        }
+        Ok(())
+    }
+
     #[test]
     fn bad_url() {
         assert!(embyfin::Client::new("/notanurl", "secret", embyfin::Fork::Jellyfin,).is_err());
@@ -443,6 +1119,46 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn paged_sessions() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let session = episode()[0].clone();
+        let first_page_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions")
+                    .query_param("StartIndex", "0")
+                    .query_param("Limit", "100");
+                then.json_body(serde_json::json!({
+                    "Items": [session, session],
+                    "TotalRecordCount": 3,
+                }));
+            })
+            .await;
+        let second_page_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/Sessions")
+                    .query_param("StartIndex", "2")
+                    .query_param("Limit", "100");
+                then.json_body(serde_json::json!({
+                    "Items": [session],
+                    "TotalRecordCount": 3,
+                }));
+            })
+            .await;
+
+        let client =
+            embyfin::Client::new(&server.url("/pathprefix"), "secret", embyfin::Fork::Emby)?;
+
+        let sessions = client.sessions().await?;
+        assert_eq!(sessions.len(), 3);
+
+        first_page_mock.assert_async().await;
+        second_page_mock.assert_async().await;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn jellyfin_auth() -> Result<(), Box<dyn std::error::Error>> {
         let server = httpmock::MockServer::start_async().await;
@@ -492,7 +1208,8 @@ mod test {
         let sessions_mock = server
             .mock_async(|when, then| {
                 when.path("/pathprefix/Sessions")
-                    .header("X-Emby-Token", "secret");
+                    .header("X-Emby-Token", "secret")
+                    .header("X-MediaBrowser-Token", "secret");
                 then.json_body(episode());
             })
             .await;
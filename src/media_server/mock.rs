@@ -0,0 +1,154 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{bail, Result};
+
+use super::{MediaServer, NowPlaying};
+
+/// Replays sessions from a file, one per poll, in a loop.
+pub struct Client {
+    sessions: Vec<NowPlaying>,
+    next: AtomicUsize,
+}
+
+impl Client {
+    pub fn new(sessions_file: &Path) -> Result<Self> {
+        let sessions = std::fs::read_to_string(sessions_file)?;
+        let sessions: Vec<NowPlaying> = serde_json::from_str(&sessions)?;
+        if sessions.is_empty() {
+            bail!("no sessions in {}", sessions_file.display());
+        }
+
+        Ok(Self {
+            sessions,
+            next: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl MediaServer for Client {
+    type Session = NowPlaying;
+    type Error = anyhow::Error;
+
+    async fn sessions(&self) -> std::prelude::v1::Result<Vec<Self::Session>, Self::Error> {
+        let next = self.next.fetch_add(1, Ordering::Relaxed) % self.sessions.len();
+        Ok(vec![self.sessions[next].clone()])
+    }
+
+    async fn extract(
+        &self,
+        session: Self::Session,
+    ) -> std::prelude::v1::Result<NowPlaying, Self::Error> {
+        Ok(session)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        media_server::{mock, MediaKind, MediaServer, NowPlaying, Series},
+        Message,
+    };
+
+    fn now_playing(series: Series, episode: i32) -> NowPlaying {
+        NowPlaying {
+            series,
+            episode,
+            season: 1,
+            user_id: "1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }
+    }
+
+    #[tokio::test]
+    async fn cycle() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sessions.json");
+        std::fs::write(
+            &path,
+            serde_json::json!([
+                {
+                    "series": { "Tvdb": 1234 },
+                    "episode": 1,
+                    "season": 1,
+                    "user_id": "1",
+                    "user_name": "user"
+                },
+                {
+                    "series": { "Title": "Test Show" },
+                    "episode": 2,
+                    "season": 1,
+                    "user_id": "1",
+                    "user_name": "user"
+                }
+            ])
+            .to_string(),
+        )?;
+
+        let client = mock::Client::new(&path)?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_millis(1), tx));
+
+        let first = now_playing(Series::Tvdb(1234), 1);
+        let second = now_playing(Series::Title("Test Show".to_string()), 2);
+        assert_eq!(rx.recv().await, Some(Message::NowPlaying(first.clone())));
+        assert_eq!(rx.recv().await, Some(Message::NowPlaying(second)));
+        assert_eq!(rx.recv().await, Some(Message::NowPlaying(first)));
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn once() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sessions.json");
+        std::fs::write(
+            &path,
+            serde_json::json!([{
+                "series": { "Tvdb": 1234 },
+                "episode": 1,
+                "season": 1,
+                "user_id": "1",
+                "user_name": "user"
+            }])
+            .to_string(),
+        )?;
+
+        let client = mock::Client::new(&path)?;
+
+        let (tx, mut rx) = mpsc::channel(2);
+        client.watch_once(tx).await;
+
+        let first = now_playing(Series::Tvdb(1234), 1);
+        assert_eq!(rx.recv().await, Some(Message::NowPlaying(first)));
+        assert_eq!(rx.recv().await, Some(Message::Shutdown));
+        // the sender is gone
+        assert_eq!(rx.recv().await, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sessions.json");
+        std::fs::write(&path, "[]")?;
+
+        assert!(mock::Client::new(&path).is_err());
+
+        Ok(())
+    }
+}
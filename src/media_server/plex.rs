@@ -1,9 +1,23 @@
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+
 use anyhow::{anyhow, bail, Result};
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::Value;
+use tracing::instrument;
+
+use super::{MediaKind, MediaServer, NowPlaying, Series};
+
+pub mod webhook;
 
-use super::{MediaServer, NowPlaying};
+/// Ask the server again for users it did not know after this long
+const ACCOUNTS_TTL: Duration = Duration::from_secs(10 * 60);
+/// User that [`OnDeckClient`] reports its items for, not a Plex account
+const ON_DECK_USER_ID: &str = "onDeck";
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -17,11 +31,17 @@ pub struct User {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Episode {
-    grandparent_title: String,
-    grandparent_key: String,
+    key: Option<String>,
+    grandparent_title: Option<String>,
+    grandparent_key: Option<String>,
+    #[serde(default)]
     index: i32,
+    #[serde(default)]
     parent_index: i32,
+    title: Option<String>,
     r#type: String,
+    view_offset: Option<i64>,
+    duration: Option<i64>,
     #[serde(rename = "User")]
     user: User,
     #[serde(flatten)]
@@ -30,7 +50,16 @@ pub struct Episode {
 
 pub struct Client {
     client: reqwest::Client,
+    headers: HeaderMap,
     url: reqwest::Url,
+    /// Account names by user ID, refreshed when a user is missing
+    accounts: RwLock<Accounts>,
+}
+
+#[derive(Default)]
+struct Accounts {
+    names: HashMap<String, String>,
+    fetched_at: Option<Instant>,
 }
 
 impl Client {
@@ -43,28 +72,37 @@ impl Client {
             reqwest::header::ACCEPT,
             HeaderValue::from_static("application/json"),
         );
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()?;
+        let client = crate::tls::client_builder(headers.clone(), None, None, None)?.build()?;
 
         let url = url.parse()?;
 
-        Ok(Self { client, url })
+        Ok(Self {
+            client,
+            headers,
+            url,
+            accounts: RwLock::default(),
+        })
+    }
+
+    /// Apply certificate and timeout settings to all further requests
+    pub fn with_options(mut self, options: &super::Options) -> Result<Self> {
+        self.client = options.client(self.headers.clone())?;
+        Ok(self)
     }
 
     async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
         let mut url = self.url.clone();
         url.path_segments_mut()
             .map_err(|()| anyhow!("url is relative"))?
-            .extend(path.split('/'));
+            .extend(path.trim_start_matches('/').split('/'));
         let response = self.client.get(url).send().await?.error_for_status()?;
         Ok(response.json::<T>().await?)
     }
 
-    async fn tvdb(&self, key: &str) -> Option<i32> {
-        self.get::<Value>(key)
-            .await
-            .ok()?
+    /// Look up the ID of an item from the most preferred provider it has
+    async fn provider_id(&self, key: &str, preference: &[&str]) -> Option<Series> {
+        let metadata = self.get::<Value>(key).await.ok()?;
+        let guids = metadata
             .get("MediaContainer")?
             .get("Metadata")?
             .as_array()?
@@ -72,11 +110,65 @@ impl Client {
             .get("Guid")?
             .as_array()?
             .iter()
-            .find_map(|g| {
-                let uri = g.as_object()?.get("id")?.as_str()?;
-                let (provider, id) = uri.split_once("://")?;
-                (provider == "tvdb").then_some(id.parse().ok()?)
+            .filter_map(|g| g.as_object()?.get("id")?.as_str()?.split_once("://"))
+            .collect::<Vec<_>>();
+        Series::from_provider_ids(&guids, preference)
+    }
+
+    /// Look up the account name of a user. Sessions of managed users in a
+    /// Plex Home may not carry the name the user is known by.
+    async fn resolve_user(&self, user_id: &str) -> Option<String> {
+        if user_id == ON_DECK_USER_ID {
+            return None;
+        }
+        {
+            let accounts = self.accounts.read().unwrap_or_else(PoisonError::into_inner);
+            if let Some(name) = accounts.names.get(user_id) {
+                return Some(name.clone());
+            }
+            // don't ask for every session of a user the server doesn't know
+            if accounts
+                .fetched_at
+                .is_some_and(|at| at.elapsed() < ACCOUNTS_TTL)
+            {
+                return None;
+            }
+        }
+
+        let names = self.fetch_accounts().await;
+        let mut accounts = self
+            .accounts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        accounts.fetched_at = Some(Instant::now());
+        if let Some(names) = names {
+            accounts.names = names;
+        }
+        accounts.names.get(user_id).cloned()
+    }
+
+    /// Account names by user ID, leaving out accounts without a name
+    async fn fetch_accounts(&self) -> Option<HashMap<String, String>> {
+        let accounts = self.get::<Value>("accounts").await.ok()?;
+        let names = accounts
+            .get("MediaContainer")?
+            .get("Account")?
+            .as_array()?
+            .iter()
+            .filter_map(|account| {
+                let id = match account.get("id")? {
+                    Value::Number(id) => id.to_string(),
+                    Value::String(id) => id.clone(),
+                    _ => return None,
+                };
+                let name = account
+                    .get("name")?
+                    .as_str()
+                    .filter(|name| !name.is_empty())?;
+                Some((id, name.to_string()))
             })
+            .collect();
+        Some(names)
     }
 
     pub async fn probe(&self) -> Result<()> {
@@ -91,45 +183,132 @@ impl MediaServer for Client {
     type Error = anyhow::Error;
 
     async fn sessions(&self) -> std::prelude::v1::Result<Vec<Self::Session>, Self::Error> {
-        let obj: serde_json::Map<String, Value> = self.get("status/sessions").await?;
-        Ok(obj
-            .get("MediaContainer")
-            .and_then(|v| v.get("Metadata"))
-            .and_then(Value::as_array)
-            .map(|metas| {
-                metas
-                    .iter()
-                    .cloned()
-                    .map(serde_json::value::from_value)
-                    .filter_map(Result::ok)
-                    .collect::<Vec<Episode>>()
-            })
-            .unwrap_or_default())
+        let obj: Value = self.get("status/sessions").await?;
+        Ok(metadata(obj)
+            .map(serde_json::value::from_value)
+            .filter_map(Result::ok)
+            .collect::<Vec<Episode>>())
     }
 
+    #[instrument(
+        name = "session",
+        skip_all,
+        fields(user = %session.user.title, series_id = ?session.grandparent_key)
+    )]
     async fn extract(
         &self,
         session: Self::Session,
     ) -> std::prelude::v1::Result<NowPlaying, Self::Error> {
-        if session.r#type != "episode" {
-            bail!("not an episode");
+        let progress_pct = session
+            .view_offset
+            .zip(session.duration)
+            .and_then(|(offset, duration)| super::progress_pct(offset, duration));
+        let user_name = self
+            .resolve_user(&session.user.id)
+            .await
+            .unwrap_or(session.user.title);
+        match session.r#type.as_str() {
+            "episode" => {}
+            "movie" => {
+                let title = session
+                    .title
+                    .ok_or_else(|| anyhow!("movie without title"))?;
+                let id = match &session.key {
+                    Some(key) => self.provider_id(key, &super::MOVIE_PROVIDERS).await,
+                    None => None,
+                };
+                return Ok(NowPlaying {
+                    series: id.unwrap_or(Series::Title(title)),
+                    episode: 0,
+                    season: 0,
+                    user_id: session.user.id,
+                    user_name,
+                    is_paused: false,
+                    position_ticks: None,
+                    progress_pct,
+                    played_at: None,
+                    kind: MediaKind::Movie,
+                });
+            }
+            _ => bail!("not an episode or movie"),
         }
+        let (Some(grandparent_title), Some(grandparent_key)) =
+            (session.grandparent_title, session.grandparent_key)
+        else {
+            bail!("episode without series");
+        };
         let episode = session.index;
         let season = session.parent_index;
-        let series = match self.tvdb(&session.grandparent_key).await {
-            Some(id) => super::Series::Tvdb(id),
-            None => super::Series::Title(session.grandparent_title),
-        };
+        let series = self
+            .provider_id(&grandparent_key, &super::SERIES_PROVIDERS)
+            .await
+            .unwrap_or(Series::Title(grandparent_title));
         Ok(NowPlaying {
             series,
             episode,
             season,
             user_id: session.user.id,
-            user_name: session.user.title,
+            user_name,
+            is_paused: false,
+            position_ticks: None,
+            progress_pct,
+            played_at: None,
+            kind: MediaKind::Episode,
         })
     }
 }
 
+/// Entries of a `MediaContainer` response
+fn metadata(mut container: Value) -> impl Iterator<Item = Value> {
+    match container
+        .get_mut("MediaContainer")
+        .and_then(|v| v.get_mut("Metadata"))
+        .map(Value::take)
+    {
+        Some(Value::Array(metas)) => metas.into_iter(),
+        _ => Vec::new().into_iter(),
+    }
+}
+
+/// Treats the items on the Plex "On Deck" list as if they were playing.
+///
+/// These are the episodes Plex expects to be watched next, so series get
+/// prefetched even while nobody is streaming. Items on deck belong to the
+/// owner of the token and are reported for the user "On Deck".
+pub struct OnDeckClient(Client);
+
+impl OnDeckClient {
+    pub fn new(client: Client) -> Self {
+        Self(client)
+    }
+}
+
+impl MediaServer for OnDeckClient {
+    type Session = Episode;
+
+    type Error = anyhow::Error;
+
+    async fn sessions(&self) -> std::prelude::v1::Result<Vec<Self::Session>, Self::Error> {
+        let obj: Value = self.0.get("library/onDeck").await?;
+        Ok(metadata(obj)
+            .filter_map(|mut item| {
+                item.as_object_mut()?.insert(
+                    "User".to_string(),
+                    serde_json::json!({ "id": ON_DECK_USER_ID, "title": "On Deck" }),
+                );
+                serde_json::from_value(item).ok()
+            })
+            .collect::<Vec<Episode>>())
+    }
+
+    async fn extract(
+        &self,
+        session: Self::Session,
+    ) -> std::prelude::v1::Result<NowPlaying, Self::Error> {
+        self.0.extract(session).await
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::time::Duration;
@@ -137,7 +316,7 @@ mod test {
     use tokio::sync::mpsc;
 
     use crate::{
-        media_server::{plex, MediaServer, NowPlaying, Series},
+        media_server::{plex, MediaKind, MediaServer, NowPlaying, Series},
         Message,
     };
 
@@ -151,6 +330,8 @@ mod test {
                         "index": 5,
                         "parentIndex": 3,
                         "type": "episode",
+                        "viewOffset": 1000,
+                        "duration": 4000,
                         "User": {
                             "id": "1",
                             "title": "user",
@@ -207,6 +388,212 @@ mod test {
             season: 3,
             user_id: "1".to_string(),
             user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: Some(25.0),
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+        series_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn managed_user() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/status/sessions");
+                then.json_body(episode());
+            })
+            .await;
+
+        let _series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/path/to/series");
+                then.json_body(series());
+            })
+            .await;
+
+        let accounts_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/accounts");
+                then.json_body(serde_json::json!({
+                    "MediaContainer": {
+                        "Account": [
+                            {"id": 0, "name": ""},
+                            {"id": 1, "name": "managed"}
+                        ]
+                    }
+                }));
+            })
+            .await;
+
+        let client = plex::Client::new(&server.url("/pathprefix"), "secret")?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 5,
+            season: 3,
+            user_id: "1".to_string(),
+            user_name: "managed".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: Some(25.0),
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+        accounts_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let accounts_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/accounts");
+                then.json_body(serde_json::json!({
+                    "MediaContainer": {
+                        "Account": [
+                            {"id": 0, "name": ""},
+                            {"id": 1, "name": "managed"}
+                        ]
+                    }
+                }));
+            })
+            .await;
+
+        let client = plex::Client::new(&server.url("/pathprefix"), "secret")?;
+
+        assert_eq!(client.resolve_user("1").await.as_deref(), Some("managed"));
+        assert_eq!(client.resolve_user("1").await.as_deref(), Some("managed"));
+        accounts_mock.assert_hits_async(1).await;
+
+        assert_eq!(client.resolve_user("2").await, None);
+        assert_eq!(client.resolve_user("2").await, None);
+        accounts_mock.assert_hits_async(2).await;
+
+        assert_eq!(client.resolve_user("onDeck").await, None);
+        accounts_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn imdb_guid() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/status/sessions");
+                then.json_body(episode());
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/path/to/series");
+                then.json_body(serde_json::json!({
+                    "MediaContainer": {
+                        "Metadata": [{
+                            "Guid": [
+                                {"id": "tvdb://"},
+                                {"id": "imdb://tt0123456"}
+                            ]
+                        }]
+                    }
+                }));
+            })
+            .await;
+
+        let client = plex::Client::new(&server.url("/pathprefix"), "secret")?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Imdb("tt0123456".to_string()),
+            episode: 5,
+            season: 3,
+            user_id: "1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: Some(25.0),
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+        series_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tmdb_guid() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/status/sessions");
+                then.json_body(episode());
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/path/to/series");
+                then.json_body(serde_json::json!({
+                    "MediaContainer": {
+                        "Metadata": [{
+                            "Guid": [
+                                {"id": "tvdb://"},
+                                {"id": "tmdb://4321"}
+                            ]
+                        }]
+                    }
+                }));
+            })
+            .await;
+
+        let client = plex::Client::new(&server.url("/pathprefix"), "secret")?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tmdb(4321),
+            episode: 5,
+            season: 3,
+            user_id: "1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: Some(25.0),
+            played_at: None,
+            kind: MediaKind::Episode,
         });
 
         assert_eq!(message, Some(message_expect));
@@ -218,6 +605,62 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn on_deck() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let on_deck_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/library/onDeck");
+                then.json_body(serde_json::json!({
+                    "MediaContainer": {
+                        "Metadata": [{
+                            "grandparentTitle": "Test Show",
+                            "grandparentKey": "path/to/series",
+                            "index": 5,
+                            "parentIndex": 3,
+                            "type": "episode"
+                        }]
+                    }
+                }));
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/path/to/series");
+                then.json_body(series());
+            })
+            .await;
+
+        let client =
+            plex::OnDeckClient::new(plex::Client::new(&server.url("/pathprefix"), "secret")?);
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 5,
+            season: 3,
+            user_id: "onDeck".to_string(),
+            user_name: "On Deck".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        on_deck_mock.assert_async().await;
+        series_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn skip_invalid_sessions() -> Result<(), Box<dyn std::error::Error>> {
         let server = httpmock::MockServer::start_async().await;
@@ -280,6 +723,11 @@ mod test {
             season: 3,
             user_id: "1".to_string(),
             user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         });
 
         assert_eq!(message, Some(message_expect));
@@ -291,6 +739,145 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn movie() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/status/sessions");
+                then.json_body(serde_json::json!(
+                    {
+                        "MediaContainer": {
+                            "Metadata": [{
+                                "title": "Test Movie",
+                                "type": "movie",
+                                "User": {
+                                    "id": "1",
+                                    "title": "user",
+                                    "thumb": "ignore"
+                                }
+                            }]
+                        }
+                    }
+                ));
+            })
+            .await;
+
+        let client = plex::Client::new(&server.url("/pathprefix"), "secret")?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Title("Test Movie".to_string()),
+            episode: 0,
+            season: 0,
+            user_id: "1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Movie,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn movie_guid() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let sessions_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/status/sessions");
+                then.json_body(serde_json::json!(
+                    {
+                        "MediaContainer": {
+                            "size": 1,
+                            "Metadata": [{
+                                "addedAt": 1_700_000_000,
+                                "duration": 8_160_000,
+                                "guid": "plex://movie/5d776824880197001ec967c6",
+                                "key": "/library/metadata/5678",
+                                "librarySectionID": "1",
+                                "ratingKey": "5678",
+                                "sessionKey": "12",
+                                "title": "Test Movie",
+                                "type": "movie",
+                                "viewOffset": 4_080_000,
+                                "year": 1999,
+                                "User": {
+                                    "id": "1",
+                                    "thumb": "https://plex.tv/users/abc/avatar",
+                                    "title": "user"
+                                },
+                                "Player": {
+                                    "product": "Plex Web",
+                                    "state": "playing"
+                                }
+                            }]
+                        }
+                    }
+                ));
+            })
+            .await;
+
+        let metadata_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/library/metadata/5678");
+                then.json_body(serde_json::json!(
+                    {
+                        "MediaContainer": {
+                            "Metadata": [{
+                                "ratingKey": "5678",
+                                "type": "movie",
+                                "title": "Test Movie",
+                                "Guid": [
+                                    { "id": "imdb://tt0133093" },
+                                    { "id": "tmdb://603" },
+                                    { "id": "tvdb://169" }
+                                ]
+                            }]
+                        }
+                    }
+                ));
+            })
+            .await;
+
+        let client = plex::Client::new(&server.url("/pathprefix"), "secret")?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = tokio::spawn(client.watch(Duration::from_secs(100), tx));
+        let message = rx.recv().await;
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tmdb(603),
+            episode: 0,
+            season: 0,
+            user_id: "1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: Some(50.0),
+            played_at: None,
+            kind: MediaKind::Movie,
+        });
+
+        assert_eq!(message, Some(message_expect));
+
+        sessions_mock.assert_async().await;
+        metadata_mock.assert_async().await;
+
+        watcher.abort();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn name_fallback() -> Result<(), Box<dyn std::error::Error>> {
         let server = httpmock::MockServer::start_async().await;
@@ -339,6 +926,11 @@ mod test {
             season: 3,
             user_id: "1".to_string(),
             user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         });
 
         assert_eq!(message, Some(message_expect));
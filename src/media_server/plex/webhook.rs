@@ -0,0 +1,243 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context as _, Result};
+use axum::{
+    extract::{Multipart, State},
+    http::StatusCode,
+    routing::post,
+    Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::mpsc};
+use tracing::debug;
+
+use super::{Client, Episode};
+use crate::{
+    media_server::{require_token, MediaServer as _, WebhookOptions},
+    Message,
+};
+
+/// Webhook payload as sent by Plex Media Server
+#[derive(Debug, Deserialize)]
+struct Payload {
+    event: String,
+    #[serde(rename = "Account")]
+    account: Account,
+    #[serde(rename = "Metadata")]
+    metadata: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Account {
+    id: Value,
+    title: String,
+}
+
+impl Payload {
+    fn is_playing(&self) -> bool {
+        matches!(self.event.as_str(), "media.play" | "media.resume")
+    }
+
+    /// Turn the payload into a session as reported by `status/sessions`
+    fn into_session(self) -> Result<Episode> {
+        let mut session = self.metadata;
+        let id = match self.account.id {
+            Value::String(id) => id,
+            id => id.to_string(),
+        };
+        session
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("metadata is not an object"))?
+            .insert(
+                "User".to_string(),
+                json!({ "id": id, "title": self.account.title }),
+            );
+        Ok(serde_json::from_value(session)?)
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    client: Arc<Client>,
+    tx: mpsc::Sender<Message>,
+}
+
+async fn payload(mut multipart: Multipart) -> Result<Payload> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("payload") {
+            return Ok(serde_json::from_str(&field.text().await?)?);
+        }
+    }
+    Err(anyhow!("no payload"))
+}
+
+async fn webhook(State(state): State<AppState>, multipart: Multipart) -> StatusCode {
+    let payload = match payload(multipart).await {
+        Ok(payload) => payload,
+        Err(e) => {
+            debug!("Ignoring webhook: {e}");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    if !payload.is_playing() {
+        debug!(event = %payload.event, "Ignoring webhook");
+        return StatusCode::NO_CONTENT;
+    }
+    let now_playing = match payload.into_session() {
+        Ok(session) => state.client.extract(session).await,
+        Err(e) => Err(e),
+    };
+    match now_playing {
+        Ok(np) => {
+            if state.tx.send(Message::NowPlaying(np)).await.is_err() {
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+        }
+        Err(e) => debug!("Ignoring webhook: {e}"),
+    }
+    StatusCode::NO_CONTENT
+}
+
+/// Receive Plex webhooks on `POST /webhook` instead of polling for sessions.
+///
+/// `client` is still needed to look up the series of an episode. Plex cannot
+/// send headers, so the secret token of `options` goes into the webhook URL.
+pub async fn listen(
+    options: &WebhookOptions,
+    client: Client,
+    tx: mpsc::Sender<Message>,
+) -> Result<()> {
+    let listener = options.bind().await?;
+    serve(listener, &options.token, client, tx).await
+}
+
+async fn serve(
+    listener: TcpListener,
+    token: &str,
+    client: Client,
+    tx: mpsc::Sender<Message>,
+) -> Result<()> {
+    let state = AppState {
+        client: Arc::new(client),
+        tx,
+    };
+    let app = Router::new().route("/webhook", post(webhook));
+    let app = require_token(app, token).with_state(state);
+    axum::serve(listener, app)
+        .await
+        .context("Webhook server failed")
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use crate::{
+        media_server::{plex, MediaKind, NowPlaying, Series},
+        Message,
+    };
+
+    fn multipart(payload: &serde_json::Value) -> String {
+        format!(
+            "--boundary\r\n\
+             Content-Disposition: form-data; name=\"payload\"\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {payload}\r\n\
+             --boundary--\r\n"
+        )
+    }
+
+    #[tokio::test]
+    async fn media_play() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/path/to/series");
+                then.json_body(json!({
+                    "MediaContainer": {
+                        "Metadata": [{
+                            "Guid": [{"id": "tvdb://1234"}]
+                        }]
+                    }
+                }));
+            })
+            .await;
+
+        let client = plex::Client::new(&server.url("/pathprefix"), "secret")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/webhook?token=secret", listener.local_addr()?);
+        let (tx, mut rx) = mpsc::channel(1);
+        let webhook_server = tokio::spawn(super::serve(listener, "secret", client, tx));
+
+        let http = reqwest::Client::new();
+        for (event, index) in [("media.pause", 4), ("media.play", 5)] {
+            let response = http
+                .post(&url)
+                .header("Content-Type", "multipart/form-data; boundary=boundary")
+                .body(multipart(&json!({
+                    "event": event,
+                    "Account": { "id": 1, "title": "user" },
+                    "Metadata": {
+                        "grandparentTitle": "Test Show",
+                        "grandparentKey": "path/to/series",
+                        "index": index,
+                        "parentIndex": 3,
+                        "type": "episode"
+                    }
+                })))
+                .send()
+                .await?;
+            assert!(response.status().is_success());
+        }
+
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 5,
+            season: 3,
+            user_id: "1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+        assert_eq!(rx.recv().await, Some(message_expect));
+
+        series_mock.assert_async().await;
+
+        webhook_server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token() -> Result<(), Box<dyn std::error::Error>> {
+        let client = plex::Client::new("http://localhost", "secret")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/webhook?token=wrong", listener.local_addr()?);
+        let (tx, mut rx) = mpsc::channel(1);
+        let webhook_server = tokio::spawn(super::serve(listener, "secret", client, tx));
+
+        let response = reqwest::Client::new()
+            .post(&url)
+            .header("Content-Type", "multipart/form-data; boundary=boundary")
+            .body(multipart(&json!({
+                "event": "media.play",
+                "Account": { "id": 1, "title": "user" },
+                "Metadata": {}
+            })))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert!(rx.try_recv().is_err());
+
+        webhook_server.abort();
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{anyhow, bail, Context as _, Result};
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use serde::Deserialize;
+use tokio::{net::TcpListener, sync::mpsc};
+use tracing::debug;
+
+use super::{
+    parse_time, progress_pct, require_token, MediaKind, NowPlaying, Series, WebhookOptions,
+    MOVIE_PROVIDERS, SERIES_PROVIDERS,
+};
+use crate::Message;
+
+/// Playback event as sent by the Jellyfin webhook plugin.
+///
+/// The plugin renders a user defined template, so the field names follow its
+/// template variables, e.g. `"SeriesName": "{{SeriesName}}"`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Event {
+    notification_type: String,
+    item_type: Option<String>,
+    name: Option<String>,
+    series_name: Option<String>,
+    season_number: Option<i32>,
+    episode_number: Option<i32>,
+    #[serde(rename = "Provider_tvdb")]
+    provider_tvdb: Option<String>,
+    #[serde(rename = "Provider_tmdb")]
+    provider_tmdb: Option<String>,
+    #[serde(rename = "Provider_imdb")]
+    provider_imdb: Option<String>,
+    user_id: String,
+    notification_username: String,
+    #[serde(default)]
+    is_paused: bool,
+    playback_position_ticks: Option<i64>,
+    run_time_ticks: Option<i64>,
+    #[serde(default)]
+    played_to_completion: bool,
+    utc_timestamp: Option<String>,
+}
+
+impl Event {
+    /// Whether playback stopped before 80% of the item
+    fn is_stopped_early(&self) -> bool {
+        if self.notification_type != "PlaybackStop" || self.played_to_completion {
+            return false;
+        }
+        match (self.playback_position_ticks, self.run_time_ticks) {
+            (Some(position), Some(run_time)) if run_time > 0 => {
+                i128::from(position) * 5 < i128::from(run_time) * 4
+            }
+            _ => false,
+        }
+    }
+
+    /// The `(provider, id)` pairs of the item that were sent
+    fn provider_ids(&self) -> Vec<(&str, &str)> {
+        [
+            ("tvdb", &self.provider_tvdb),
+            ("tmdb", &self.provider_tmdb),
+            ("imdb", &self.provider_imdb),
+        ]
+        .into_iter()
+        .filter_map(|(provider, id)| Some((provider, id.as_deref()?)))
+        .collect()
+    }
+
+    fn into_now_playing(self) -> Result<NowPlaying> {
+        let (series, episode, season, kind) = match self.item_type.as_deref() {
+            Some("Episode") => {
+                let series = Series::from_provider_ids(&self.provider_ids(), &SERIES_PROVIDERS);
+                let series = match series {
+                    Some(series) => series,
+                    None => Series::Title(
+                        self.series_name
+                            .ok_or_else(|| anyhow!("episode without series"))?,
+                    ),
+                };
+                let episode = self
+                    .episode_number
+                    .ok_or_else(|| anyhow!("episode without number"))?;
+                let season = self
+                    .season_number
+                    .ok_or_else(|| anyhow!("episode without season"))?;
+                (series, episode, season, MediaKind::Episode)
+            }
+            Some("Movie") => {
+                let movie = Series::from_provider_ids(&self.provider_ids(), &MOVIE_PROVIDERS);
+                let movie = match movie {
+                    Some(movie) => movie,
+                    None => Series::Title(self.name.ok_or_else(|| anyhow!("movie without title"))?),
+                };
+                (movie, 0, 0, MediaKind::Movie)
+            }
+            _ => bail!("not an episode or movie"),
+        };
+
+        Ok(NowPlaying {
+            series,
+            episode,
+            season,
+            user_id: self.user_id,
+            user_name: self.notification_username,
+            is_paused: self.is_paused,
+            position_ticks: self.playback_position_ticks,
+            progress_pct: self
+                .playback_position_ticks
+                .zip(self.run_time_ticks)
+                .and_then(|(position, run_time)| progress_pct(position, run_time)),
+            played_at: self.utc_timestamp.as_deref().and_then(parse_time),
+            kind,
+        })
+    }
+}
+
+impl TryFrom<Event> for NowPlaying {
+    type Error = anyhow::Error;
+
+    fn try_from(event: Event) -> Result<Self> {
+        if !matches!(
+            event.notification_type.as_str(),
+            "PlaybackStart" | "PlaybackProgress"
+        ) {
+            bail!("not a playback event");
+        }
+
+        event.into_now_playing()
+    }
+}
+
+async fn webhook(State(tx): State<mpsc::Sender<Message>>, Json(event): Json<Event>) -> StatusCode {
+    let message = if event.is_stopped_early() {
+        event.into_now_playing().map(Message::Stopped)
+    } else {
+        NowPlaying::try_from(event).map(Message::NowPlaying)
+    };
+    match message {
+        Ok(message) => {
+            if tx.send(message).await.is_err() {
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+        }
+        Err(e) => debug!("Ignoring webhook: {e}"),
+    }
+    StatusCode::NO_CONTENT
+}
+
+/// Receive Jellyfin playback events on `POST /webhook` instead of polling
+/// for sessions.
+///
+/// Stopping playback before 80% of an episode or movie allows searching for
+/// it again once playback resumes. Requests without the secret token of
+/// `options` are rejected.
+pub async fn listen(options: &WebhookOptions, tx: mpsc::Sender<Message>) -> Result<()> {
+    let listener = options.bind().await?;
+    serve(listener, &options.token, tx).await
+}
+
+async fn serve(listener: TcpListener, token: &str, tx: mpsc::Sender<Message>) -> Result<()> {
+    let app = Router::new().route("/webhook", post(webhook));
+    let app = require_token(app, token).with_state(tx);
+    axum::serve(listener, app)
+        .await
+        .context("Webhook server failed")
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use crate::{
+        media_server::{MediaKind, NowPlaying, Series},
+        Message,
+    };
+
+    #[tokio::test]
+    async fn playback_start() -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/webhook?token=secret", listener.local_addr()?);
+        let (tx, mut rx) = mpsc::channel(1);
+        let server = tokio::spawn(super::serve(listener, "secret", tx));
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(&url)
+            .json(&json!({
+                "NotificationType": "PlaybackStart",
+                "ItemType": "Episode",
+                "Name": "Pilot",
+                "SeriesName": "Test Show",
+                "SeasonNumber": 3,
+                "EpisodeNumber": 5,
+                "Provider_tvdb": "1234",
+                "UserId": "08ba1929-681e-4b24-929b-9245852f65c0",
+                "NotificationUsername": "user",
+                "PlaybackPositionTicks": 12_345_678,
+                "UtcTimestamp": "2024-11-30T20:15:00.0000000Z",
+            }))
+            .send()
+            .await?;
+        assert!(response.status().is_success());
+
+        let message_expect = Message::NowPlaying(NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 5,
+            season: 3,
+            user_id: "08ba1929-681e-4b24-929b-9245852f65c0".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: Some(12_345_678),
+            progress_pct: None,
+            played_at: "2024-11-30T20:15:00Z".parse().ok(),
+            kind: MediaKind::Episode,
+        });
+        assert_eq!(rx.recv().await, Some(message_expect));
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn token() -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/webhook", listener.local_addr()?);
+        let (tx, mut rx) = mpsc::channel(1);
+        let server = tokio::spawn(super::serve(listener, "secret", tx));
+
+        let client = reqwest::Client::new();
+        let event = json!({
+            "NotificationType": "PlaybackStart",
+            "ItemType": "Episode",
+            "SeriesName": "Test Show",
+            "SeasonNumber": 3,
+            "EpisodeNumber": 5,
+            "UserId": "1",
+            "NotificationUsername": "user",
+        });
+        for request in [
+            client.post(&url),
+            client.post(format!("{url}?token=wrong")),
+            client.post(&url).header("X-Prefetcharr-Token", "wrong"),
+        ] {
+            let response = request.json(&event).send().await?;
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        }
+        let response = client
+            .post(&url)
+            .header("X-Prefetcharr-Token", "secret")
+            .json(&event)
+            .send()
+            .await?;
+        assert!(response.status().is_success());
+
+        assert!(matches!(rx.recv().await, Some(Message::NowPlaying(_))));
+        assert!(rx.try_recv().is_err());
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn playback_stop() -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/webhook?token=secret", listener.local_addr()?);
+        let (tx, mut rx) = mpsc::channel(2);
+        let server = tokio::spawn(super::serve(listener, "secret", tx));
+
+        let client = reqwest::Client::new();
+        for (episode, position) in [(4, 95), (5, 30)] {
+            let response = client
+                .post(&url)
+                .json(&json!({
+                    "NotificationType": "PlaybackStop",
+                    "ItemType": "Episode",
+                    "SeriesName": "Test Show",
+                    "SeasonNumber": 3,
+                    "EpisodeNumber": episode,
+                    "Provider_tvdb": "1234",
+                    "UserId": "1",
+                    "NotificationUsername": "user",
+                    "PlaybackPositionTicks": position,
+                    "RunTimeTicks": 100,
+                }))
+                .send()
+                .await?;
+            assert!(response.status().is_success());
+        }
+
+        let message_expect = Message::Stopped(NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 5,
+            season: 3,
+            user_id: "1".to_string(),
+            user_name: "user".to_string(),
+            is_paused: false,
+            position_ticks: Some(30),
+            progress_pct: Some(30.0),
+            played_at: None,
+            kind: MediaKind::Episode,
+        });
+        assert_eq!(rx.recv().await, Some(message_expect));
+
+        server.abort();
+        Ok(())
+    }
+
+    #[test]
+    fn convert() -> Result<(), Box<dyn std::error::Error>> {
+        let event = |value| serde_json::from_value::<super::Event>(value);
+
+        let np = NowPlaying::try_from(event(json!({
+            "NotificationType": "PlaybackProgress",
+            "ItemType": "Episode",
+            "SeriesName": "Test Show",
+            "SeasonNumber": 1,
+            "EpisodeNumber": 2,
+            "Provider_tvdb": "",
+            "UserId": "1",
+            "NotificationUsername": "user",
+            "IsPaused": true,
+        }))?)?;
+        assert_eq!(np.series, Series::Title("Test Show".to_string()));
+        assert!(np.is_paused);
+
+        let np = NowPlaying::try_from(event(json!({
+            "NotificationType": "PlaybackStart",
+            "ItemType": "Movie",
+            "Name": "Test Movie",
+            "UserId": "1",
+            "NotificationUsername": "user",
+        }))?)?;
+        assert_eq!(np.series, Series::Title("Test Movie".to_string()));
+        assert_eq!(np.kind, MediaKind::Movie);
+
+        let np = NowPlaying::try_from(event(json!({
+            "NotificationType": "PlaybackStart",
+            "ItemType": "Movie",
+            "Name": "Test Movie",
+            "Provider_imdb": "tt0123456",
+            "Provider_tmdb": "1234",
+            "UserId": "1",
+            "NotificationUsername": "user",
+        }))?)?;
+        assert_eq!(np.series, Series::Tmdb(1234));
+
+        let np = NowPlaying::try_from(event(json!({
+            "NotificationType": "PlaybackStart",
+            "ItemType": "Episode",
+            "SeriesName": "Test Show",
+            "SeasonNumber": 1,
+            "EpisodeNumber": 2,
+            "Provider_imdb": "tt0123456",
+            "UserId": "1",
+            "NotificationUsername": "user",
+        }))?)?;
+        assert_eq!(np.series, Series::Imdb("tt0123456".to_string()));
+
+        assert!(NowPlaying::try_from(event(json!({
+            "NotificationType": "PlaybackStop",
+            "ItemType": "Episode",
+            "SeriesName": "Test Show",
+            "SeasonNumber": 1,
+            "EpisodeNumber": 2,
+            "UserId": "1",
+            "NotificationUsername": "user",
+        }))?)
+        .is_err());
+
+        assert!(NowPlaying::try_from(event(json!({
+            "NotificationType": "PlaybackStart",
+            "ItemType": "Audio",
+            "Name": "Song",
+            "UserId": "1",
+            "NotificationUsername": "user",
+        }))?)
+        .is_err());
+
+        Ok(())
+    }
+}
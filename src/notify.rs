@@ -0,0 +1,104 @@
+use anyhow::Result;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Url,
+};
+use serde_json::{json, Value};
+use tracing::warn;
+
+/// Posts a JSON message to every configured URL after a search was sent.
+pub struct Notifier {
+    client: reqwest::Client,
+    urls: Vec<Url>,
+}
+
+impl Notifier {
+    pub fn new(urls: &[String], headers: &[(String, String)]) -> Result<Self> {
+        let urls = urls
+            .iter()
+            .map(|url| url.parse())
+            .collect::<Result<Vec<Url>, _>>()?;
+
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            let mut value = HeaderValue::from_str(value)?;
+            value.set_sensitive(true);
+            header_map.insert(HeaderName::try_from(name)?, value);
+        }
+        let client = crate::tls::client_builder(header_map, None, None, None)?.build()?;
+
+        Ok(Self { client, urls })
+    }
+
+    pub async fn season_searched(&self, series: &str, season: i32, episodes: &[i32]) {
+        self.send(&json!({
+            "series": series,
+            "season": season,
+            "episodes": episodes,
+        }))
+        .await;
+    }
+
+    pub async fn movie_searched(&self, movie: &str) {
+        self.send(&json!({ "movie": movie })).await;
+    }
+
+    async fn send(&self, message: &Value) {
+        for url in &self.urls {
+            let result = self
+                .client
+                .post(url.clone())
+                .json(message)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            if let Err(e) = result {
+                warn!("Cannot send notification to {url}: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::Method::POST;
+    use serde_json::json;
+
+    use crate::notify::Notifier;
+
+    #[tokio::test]
+    async fn season_searched() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let notification_mock = server
+            .mock_async(|when, then| {
+                when.path("/notify")
+                    .method(POST)
+                    .header("Authorization", "Bearer token")
+                    .json_body(json!({
+                        "series": "TestShow",
+                        "season": 2,
+                        "episodes": [1, 2, 3],
+                    }));
+                then.status(200);
+            })
+            .await;
+        let failing_mock = server
+            .mock_async(|when, then| {
+                when.path("/failing");
+                then.status(500);
+            })
+            .await;
+
+        let notifier = Notifier::new(
+            &[server.url("/failing"), server.url("/notify")],
+            &[("Authorization".to_string(), "Bearer token".to_string())],
+        )?;
+        notifier.season_searched("TestShow", 2, &[1, 2, 3]).await;
+
+        failing_mock.assert_async().await;
+        notification_mock.assert_async().await;
+
+        Ok(())
+    }
+}
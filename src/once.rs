@@ -1,9 +1,16 @@
 use std::{
     collections::HashSet,
     hash::Hash,
-    time::{Duration, Instant},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
 use crate::media_server::Series;
 
 const RETAIN_DURATION: Duration = Duration::from_secs(60 * 60 * 24 * 7);
@@ -42,19 +49,210 @@ impl Hash for Entry {
     }
 }
 
-#[derive(Default)]
-pub struct Seen(HashSet<Entry>);
+/// On-disk representation of an [`Entry`]
+#[derive(Serialize, Deserialize)]
+struct Record {
+    series: Series,
+    season: i32,
+    /// Seconds since the UNIX epoch
+    touched: u64,
+}
+
+pub struct Seen {
+    entries: HashSet<Entry>,
+    /// How long to remember an entry after it was last touched
+    retention: Duration,
+    /// File to keep up to date with every change
+    path: Option<PathBuf>,
+    /// Number of the latest snapshot handed off for saving
+    generation: u64,
+    /// Number of the latest snapshot on disk, held while writing
+    written: Arc<Mutex<u64>>,
+}
+
+impl Default for Seen {
+    fn default() -> Self {
+        Self::new(RETAIN_DURATION)
+    }
+}
 
 impl Seen {
-    pub fn once(&mut self, series: Series, season: i32) -> bool {
-        self.prune();
-        self.0.replace(Entry::new(series, season)).is_none()
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            entries: HashSet::new(),
+            retention,
+            path: None,
+            generation: 0,
+            written: Arc::default(),
+        }
+    }
+
+    /// Restore the entries saved to `path` and keep saving changes there.
+    ///
+    /// A missing or unreadable file results in an empty cache.
+    pub fn load(path: &Path, retention: Duration) -> Result<Self> {
+        let mut seen = Self {
+            path: Some(path.to_path_buf()),
+            ..Self::new(retention)
+        };
+        if !path.exists() {
+            return Ok(seen);
+        }
+
+        let records: Vec<Record> = match serde_json::from_str(&std::fs::read_to_string(path)?) {
+            Ok(records) => records,
+            Err(e) => {
+                warn!(
+                    "Cannot parse seen items in {}, starting over: {e}",
+                    path.display()
+                );
+                return Ok(seen);
+            }
+        };
+        let now = SystemTime::now();
+        for record in records {
+            let touched = UNIX_EPOCH + Duration::from_secs(record.touched);
+            let age = now.duration_since(touched).unwrap_or_default();
+            if age > seen.retention {
+                continue;
+            }
+            if let Some(touched) = Instant::now().checked_sub(age) {
+                seen.entries.replace(Entry {
+                    season: Season {
+                        series: record.series,
+                        season: record.season,
+                    },
+                    touched,
+                });
+            }
+        }
+        Ok(seen)
+    }
+
+    pub fn retention(&self) -> Duration {
+        self.retention
     }
 
-    fn prune(&mut self) {
-        self.0
-            .retain(|e| Instant::now().saturating_duration_since(e.touched) <= RETAIN_DURATION);
+    /// Stop saving changes, so a dry run doesn't hold back later searches
+    pub fn without_saving(mut self) -> Self {
+        self.path = None;
+        self
     }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, &serde_json::to_string(&self.records())?)
+    }
+
+    fn records(&self) -> Vec<Record> {
+        let now = SystemTime::now();
+        self.entries
+            .iter()
+            .filter(|e| e.touched.elapsed() <= self.retention)
+            .map(|e| Record {
+                series: e.season.series.clone(),
+                season: e.season.season,
+                touched: (now - e.touched.elapsed())
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+            .collect()
+    }
+
+    /// Remember a season unless it is known already, dated back to when it
+    /// was played if that is recent enough to be plausible
+    pub fn once(&mut self, series: Series, season: i32, played_at: Option<DateTime<Utc>>) -> bool {
+        let pruned = self.prune();
+        let mut entry = Entry::new(series, season);
+        if let Some(touched) = played_at
+            .and_then(|at| (Utc::now() - at).to_std().ok())
+            .filter(|age| *age <= self.retention)
+            .and_then(|age| Instant::now().checked_sub(age))
+        {
+            entry.touched = touched;
+        }
+        let new = self.entries.replace(entry).is_none();
+        if new || pruned {
+            self.changed();
+        }
+        new
+    }
+
+    /// Drop an entry so the next [`Seen::once`] for it succeeds again
+    pub fn forget(&mut self, series: &Series, season: i32) -> bool {
+        let removed = self.entries.remove(&Entry::new(series.clone(), season));
+        if removed {
+            self.changed();
+        }
+        removed
+    }
+
+    /// Save a snapshot of the entries if there is a file to keep up to date.
+    ///
+    /// Only the serialization happens in place. The file is written on the
+    /// blocking pool when there is a runtime, so callers holding a lock on
+    /// `self` don't stall other tasks on disk IO.
+    fn changed(&mut self) {
+        #[allow(clippy::cast_precision_loss)]
+        metrics::gauge!(crate::prometheus::SEEN_CACHE_SIZE).set(self.entries.len() as f64);
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let json = match serde_json::to_string(&self.records()) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!("Cannot serialize seen items: {e}");
+                return;
+            }
+        };
+        self.generation += 1;
+        let generation = self.generation;
+        let written = Arc::clone(&self.written);
+        let write = move || {
+            let mut written = written.lock().unwrap_or_else(PoisonError::into_inner);
+            // a later snapshot got here first
+            if *written >= generation {
+                return;
+            }
+            match write_atomic(&path, &json) {
+                Ok(()) => *written = generation,
+                Err(e) => warn!("Cannot save seen items to {}: {e:#}", path.display()),
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(write)),
+            Err(_) => write(),
+        }
+    }
+
+    pub fn contains(&self, series: &Series, season: i32) -> bool {
+        self.contains_at(series, season, Instant::now())
+    }
+
+    fn contains_at(&self, series: &Series, season: i32, now: Instant) -> bool {
+        self.entries
+            .get(&Entry::new(series.clone(), season))
+            .is_some_and(|e| now.saturating_duration_since(e.touched) <= self.retention)
+    }
+
+    /// Drop expired entries, returning whether there were any
+    fn prune(&mut self) -> bool {
+        let retention = self.retention;
+        let len = self.entries.len();
+        self.entries
+            .retain(|e| Instant::now().saturating_duration_since(e.touched) <= retention);
+        self.entries.len() != len
+    }
+}
+
+/// Replace `path` with `contents` so that a crash while writing doesn't leave
+/// a truncated file behind
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -71,8 +269,8 @@ mod test {
         let mut seen = Seen::default();
         let series = Series::Tvdb(1);
         let season = 3;
-        assert!(seen.once(series.clone(), season));
-        assert!(!seen.once(series, season));
+        assert!(seen.once(series.clone(), season, None));
+        assert!(!seen.once(series, season, None));
     }
 
     #[test]
@@ -84,8 +282,8 @@ mod test {
         let mut old = Entry::new(series.clone(), season);
         old.touched = Instant::now().checked_sub(super::RETAIN_DURATION).unwrap();
 
-        seen.0.replace(old);
-        assert!(seen.once(series, season));
+        seen.entries.replace(old);
+        assert!(seen.once(series, season, None));
     }
 
     #[test]
@@ -99,26 +297,163 @@ mod test {
             .checked_sub(super::RETAIN_DURATION)
             .unwrap();
 
-        seen.0.replace(old);
-        assert!(!seen.once(series.clone(), season));
+        seen.entries.replace(old);
+        assert!(!seen.once(series.clone(), season, None));
 
         std::thread::sleep(Duration::from_millis(100));
-        assert!(!seen.once(series, season));
+        assert!(!seen.once(series, season, None));
+    }
+
+    #[test]
+    fn played_at() {
+        let mut seen = Seen::new(Duration::from_secs(60));
+        let series = Series::Tvdb(1);
+        let played_at = chrono::Utc::now() - chrono::Duration::seconds(59);
+        assert!(seen.once(series.clone(), 1, Some(played_at)));
+        let later = Instant::now() + Duration::from_secs(2);
+        assert!(seen.contains(&series, 1));
+        assert!(!seen.contains_at(&series, 1, later));
+
+        let ancient = chrono::DateTime::UNIX_EPOCH;
+        assert!(seen.once(series.clone(), 2, Some(ancient)));
+        assert!(seen.contains(&series, 2));
+    }
+
+    #[test]
+    fn forget() {
+        let mut seen = Seen::default();
+        let series = Series::Tvdb(1);
+        assert!(seen.once(series.clone(), 1, None));
+        assert!(seen.forget(&series, 1));
+        assert!(!seen.forget(&series, 1));
+        assert!(seen.once(series, 1, None));
+    }
+
+    #[test]
+    fn contains() {
+        let mut seen = Seen::default();
+        let series = Series::Tvdb(1);
+        assert!(!seen.contains(&series, 1));
+        seen.once(series.clone(), 1, None);
+        assert!(seen.contains(&series, 1));
+        assert!(!seen.contains(&series, 2));
+    }
+
+    #[test]
+    fn persist() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("seen.json");
+
+        let mut seen = Seen::load(&path, super::RETAIN_DURATION)?;
+        assert!(seen.once(Series::Tvdb(1), 1, None));
+        assert!(seen.once(Series::Title("Test Show".to_string()), 2, None));
+
+        let mut old = Entry::new(Series::Tvdb(2), 3);
+        old.touched = Instant::now()
+            .checked_sub(super::RETAIN_DURATION * 2)
+            .unwrap();
+        seen.entries.replace(old);
+        seen.save(&path)?;
+
+        let mut seen = Seen::load(&path, super::RETAIN_DURATION)?;
+        assert_eq!(seen.entries.len(), 2);
+        assert!(!seen.once(Series::Tvdb(1), 1, None));
+        assert!(!seen.once(Series::Title("Test Show".to_string()), 2, None));
+        assert!(seen.once(Series::Tvdb(2), 3, None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_file() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("seen.json");
+        std::fs::write(&path, "[{\"series\":")?;
+
+        let mut seen = Seen::load(&path, super::RETAIN_DURATION)?;
+        assert!(seen.entries.is_empty());
+        assert!(seen.once(Series::Tvdb(1), 1, None));
+
+        let seen = Seen::load(&path, super::RETAIN_DURATION)?;
+        assert_eq!(seen.entries.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn without_saving() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("seen.json");
+
+        let mut seen = Seen::load(&path, super::RETAIN_DURATION)?.without_saving();
+        assert!(seen.once(Series::Tvdb(1), 1, None));
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn save_only_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("seen.json");
+
+        let mut seen = Seen::load(&path, super::RETAIN_DURATION)?;
+        assert!(seen.once(Series::Tvdb(1), 1, None));
+        std::fs::remove_file(&path)?;
+
+        assert!(!seen.once(Series::Tvdb(1), 1, None));
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn save_in_background() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("seen.json");
+
+        let mut seen = Seen::load(&path, super::RETAIN_DURATION)?;
+        assert!(seen.once(Series::Tvdb(1), 1, None));
+        assert!(seen.once(Series::Tvdb(2), 1, None));
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while *seen.written.lock().unwrap() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        let saved = Seen::load(&path, super::RETAIN_DURATION)?;
+        assert_eq!(saved.entries.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn retention() {
+        let mut seen = Seen::new(Duration::from_secs(60));
+        let series = Series::Tvdb(1);
+        let season = 3;
+
+        let mut old = Entry::new(series.clone(), season);
+        old.touched = Instant::now().checked_sub(Duration::from_secs(61)).unwrap();
+
+        seen.entries.replace(old);
+        assert!(seen.once(series, season, None));
     }
 
     #[test]
     fn different_season() {
         let mut seen = Seen::default();
         let series = Series::Tvdb(1);
-        assert!(seen.once(series.clone(), 1));
-        assert!(seen.once(series, 2));
+        assert!(seen.once(series.clone(), 1, None));
+        assert!(seen.once(series, 2, None));
     }
 
     #[test]
     fn different_series() {
         let mut seen = Seen::default();
         let season = 1;
-        assert!(seen.once(Series::Tvdb(1), season));
-        assert!(seen.once(Series::Tvdb(2), season));
+        assert!(seen.once(Series::Tvdb(1), season, None));
+        assert!(seen.once(Series::Tvdb(2), season, None));
     }
 }
@@ -0,0 +1,130 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Url,
+};
+use serde_json::{json, Value};
+
+/// Requests seasons through Overseerr or Jellyseerr, which pass them on to Sonarr
+pub struct Client {
+    base_url: Url,
+    client: reqwest::Client,
+    dry_run: bool,
+}
+
+/// Optional connection settings
+#[derive(Default)]
+pub struct Options {
+    /// Only log requests that would be created in Overseerr
+    pub dry_run: bool,
+    /// Trust only the certificate authorities in this PEM file
+    pub ca_cert: Option<PathBuf>,
+    /// Give up on requests that take longer than this
+    pub request_timeout: Option<Duration>,
+    /// Give up on connecting after this long
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Client {
+    pub fn new(base_url: &str, api_key: &str, options: &Options) -> Result<Self> {
+        let mut api_key = HeaderValue::from_str(api_key)?;
+        api_key.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Api-Key", api_key);
+        headers.insert(
+            reqwest::header::ACCEPT,
+            HeaderValue::from_static("application/json"),
+        );
+
+        let client = crate::tls::client_builder(
+            headers,
+            options.ca_cert.as_deref(),
+            options.request_timeout,
+            options.connect_timeout,
+        )?
+        .build()?;
+
+        let base_url = crate::http::base_url(base_url)?;
+
+        Ok(Self {
+            base_url,
+            client,
+            dry_run: options.dry_run,
+        })
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        crate::http::api_url(&self.base_url, &["api", "v1"], path)
+    }
+
+    pub async fn probe(&self) -> Result<()> {
+        self.client
+            .get(self.url("status")?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Request seasons of a series, which Overseerr identifies by its TMDB ID
+    pub async fn request_tv(&self, tmdb_id: i32, tvdb_id: i32, seasons: &[i32]) -> Result<Value> {
+        let body = json!({
+            "mediaType": "tv",
+            "mediaId": tmdb_id,
+            "tvdbId": tvdb_id,
+            "seasons": seasons,
+        });
+
+        let url = self.url("request")?;
+        if crate::http::is_dry_run(self.dry_run, "POST", &url, &body) {
+            return Ok(Value::Null);
+        }
+        let response = self
+            .client
+            .post(url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::Method::POST;
+    use serde_json::json;
+
+    use crate::overseerr::Options;
+
+    #[tokio::test]
+    async fn request_tv() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let request_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v1/request")
+                    .method(POST)
+                    .header("X-Api-Key", "secret")
+                    .json_body(json!({
+                        "mediaType": "tv",
+                        "mediaId": 1234,
+                        "tvdbId": 5678,
+                        "seasons": [2],
+                    }));
+                then.status(201).json_body(json!({ "id": 1 }));
+            })
+            .await;
+        let client =
+            super::Client::new(&server.url("/pathprefix/"), "secret", &Options::default())?;
+
+        client.request_tv(1234, 5678, &[2]).await?;
+
+        request_mock.assert_async().await;
+
+        Ok(())
+    }
+}
@@ -1,19 +1,64 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
 use anyhow::anyhow;
-use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task::JoinSet,
+};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    media_server::{NowPlaying, Series},
+    filter::SeriesFilter,
+    media_server::{MediaKind, NowPlaying, Series},
+    notify::Notifier,
     once::Seen,
+    overseerr, prometheus, radarr,
+    session_log::SessionLog,
     sonarr, Message,
 };
 
+const RESCAN_DELAY: Duration = Duration::from_secs(60);
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+/// Give up the permit of a season search that Sonarr didn't complete by then
+const SEARCH_PERMIT_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
 pub struct Actor {
     rx: mpsc::Receiver<Message>,
+    handler: Handler,
+    /// Abort tasks still running this long after a shutdown message
+    shutdown_timeout: Option<Duration>,
+}
+
+/// Everything needed to process a single message, shared by concurrent tasks
+struct Handler {
     sonarr_client: sonarr::Client,
-    seen: Seen,
+    seen: Mutex<Seen>,
     remaining_episodes: u8,
     users: Vec<String>,
+    excluded_users: Vec<String>,
+    min_progress: f32,
+    quality_profile_id: Option<i32>,
+    session_log: Option<SessionLog>,
+    radarr_client: Option<radarr::Client>,
+    overseerr_client: Option<overseerr::Client>,
+    required_tags: Vec<String>,
+    required_quality_profile_ids: Vec<i32>,
+    series: Vec<String>,
+    series_filter: SeriesFilter,
+    notifier: Option<Notifier>,
+    /// Wait this long after a season search before asking Sonarr to rescan the series
+    rescan_delay: Option<Duration>,
+    rename_after_download: bool,
+    /// Permits for season searches running in Sonarr at the same time
+    searches: Option<Semaphore>,
+    /// Episodes handled within the seen retention, to spare Sonarr repeated lookups
+    dispatched: Mutex<HashMap<(Series, i32, i32), Instant>>,
+    dispatch_retention: Duration,
 }
 
 impl Actor {
@@ -24,58 +69,485 @@ impl Actor {
         remaining_episodes: u8,
         users: Vec<String>,
     ) -> Self {
+        let dispatch_retention = seen.retention();
         Self {
             rx,
-            sonarr_client,
-            seen,
-            remaining_episodes,
-            users,
+            handler: Handler {
+                sonarr_client,
+                seen: Mutex::new(seen),
+                remaining_episodes,
+                users,
+                excluded_users: Vec::new(),
+                min_progress: 0.0,
+                quality_profile_id: None,
+                session_log: None,
+                radarr_client: None,
+                overseerr_client: None,
+                required_tags: Vec::new(),
+                required_quality_profile_ids: Vec::new(),
+                series: Vec::new(),
+                series_filter: SeriesFilter::default(),
+                notifier: None,
+                rescan_delay: None,
+                rename_after_download: false,
+                searches: None,
+                dispatched: Mutex::new(HashMap::new()),
+                dispatch_retention,
+            },
+            shutdown_timeout: None,
         }
     }
+
+    /// Never react to sessions of these users, given by ID or name
+    pub fn with_excluded_users(mut self, excluded_users: Vec<String>) -> Self {
+        self.handler.excluded_users = excluded_users;
+        self
+    }
+
+    /// Ignore sessions until they reach this percentage of the item
+    pub fn with_min_progress(mut self, min_progress: f32) -> Self {
+        self.handler.min_progress = min_progress;
+        self
+    }
+
+    /// Prefetch movies through Radarr, these are ignored otherwise
+    pub fn with_radarr(mut self, radarr_client: radarr::Client) -> Self {
+        self.handler.radarr_client = Some(radarr_client);
+        self
+    }
+
+    /// Request seasons through Overseerr instead of searching them in Sonarr
+    pub fn with_overseerr(mut self, overseerr_client: overseerr::Client) -> Self {
+        self.handler.overseerr_client = Some(overseerr_client);
+        self
+    }
+
+    /// Log every incoming session to this file
+    pub fn with_session_log(mut self, session_log: SessionLog) -> Self {
+        self.handler.session_log = Some(session_log);
+        self
+    }
+
+    /// Assign this quality profile to series before searching them
+    pub fn with_quality_profile(mut self, profile_id: i32) -> Self {
+        self.handler.quality_profile_id = Some(profile_id);
+        self
+    }
+
+    /// Only manage series with at least one of these Sonarr tags
+    pub fn with_required_tags(mut self, required_tags: Vec<String>) -> Self {
+        self.handler.required_tags = required_tags;
+        self
+    }
+
+    /// Only manage series that use one of these quality profiles
+    pub fn with_required_quality_profile_ids(mut self, ids: Vec<i32>) -> Self {
+        self.handler.required_quality_profile_ids = ids;
+        self
+    }
+
+    /// Only manage these series, given by title or TVDB ID
+    pub fn with_series(mut self, series: Vec<String>) -> Self {
+        self.handler.series = series;
+        self
+    }
+
+    /// Only manage series with a title matching the filter
+    pub fn with_series_filter(mut self, series_filter: SeriesFilter) -> Self {
+        self.handler.series_filter = series_filter;
+        self
+    }
+
+    /// Run at most this many season searches at the same time. A search
+    /// holds its permit until Sonarr completed it.
+    pub fn with_max_concurrent_searches(mut self, max: usize) -> Self {
+        self.handler.searches = Some(Semaphore::new(max));
+        self
+    }
+
+    /// Rescan the files of a series a minute after searching it
+    pub fn with_rescan_after_search(mut self) -> Self {
+        self.handler.rescan_delay = Some(RESCAN_DELAY);
+        self
+    }
+
+    /// Rename the files of a series once the searched season left the download queue
+    pub fn with_rename_after_download(mut self) -> Self {
+        self.handler.rename_after_download = true;
+        self
+    }
+
+    /// Give sessions still being processed this long to finish on shutdown
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Announce every search that was sent
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.handler.notifier = Some(notifier);
+        self
+    }
 }
 
 impl Actor {
+    /// Handle messages until shutdown, processing sessions concurrently
+    pub async fn process(self) {
+        let Self {
+            mut rx,
+            handler,
+            shutdown_timeout,
+        } = self;
+        let handler = Arc::new(handler);
+        let mut tasks = JoinSet::new();
+        let spawn = |tasks: &mut JoinSet<_>, np| {
+            let handler = Arc::clone(&handler);
+            tasks.spawn(async move { handler.now_playing(np).await });
+        };
+
+        let timeout = loop {
+            tokio::select! {
+                Some(result) = tasks.join_next() => log_result(result),
+                msg = rx.recv() => match msg {
+                    Some(Message::NowPlaying(np)) => spawn(&mut tasks, np),
+                    Some(Message::Stopped(np)) => handler.stopped(&np),
+                    Some(Message::Shutdown) => {
+                        debug!("draining queued messages before shutdown");
+                        rx.close();
+                        while let Ok(Message::NowPlaying(np)) = rx.try_recv() {
+                            spawn(&mut tasks, np);
+                        }
+                        break shutdown_timeout;
+                    }
+                    None => break None,
+                },
+            }
+        };
+
+        let drain = async {
+            while let Some(result) = tasks.join_next().await {
+                log_result(result);
+            }
+        };
+        match timeout {
+            Some(timeout) => {
+                if tokio::time::timeout(timeout, drain).await.is_err() {
+                    warn!(
+                        tasks = tasks.len(),
+                        "Processing did not finish in time, aborting"
+                    );
+                    tasks.shutdown().await;
+                }
+            }
+            None => drain.await,
+        }
+    }
+}
+
+fn log_result(result: Result<anyhow::Result<()>, tokio::task::JoinError>) {
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!(err = ?e, "Failed to process"),
+        Err(e) => error!(err = ?e, "Processing task failed"),
+    }
+}
+
+impl Handler {
+    fn seen(&self) -> MutexGuard<'_, Seen> {
+        self.seen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn dispatched(&self) -> MutexGuard<'_, HashMap<(Series, i32, i32), Instant>> {
+        self.dispatched
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Remember an episode as being handled, unless it was handled recently
+    fn dispatch(&self, key: (Series, i32, i32)) -> bool {
+        let now = Instant::now();
+        let mut dispatched = self.dispatched();
+        dispatched.retain(|_, at| now.saturating_duration_since(*at) <= self.dispatch_retention);
+        match dispatched.entry(key) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                true
+            }
+        }
+    }
+
     fn is_user_wanted(&self, np: &NowPlaying) -> bool {
-        if self.users.is_empty() {
+        // Match either the user ID or user name, ignoring case and
+        // surrounding whitespace.
+        let id = np.user_id.trim();
+        let name = np.user_name.trim();
+        let matches = |user: &String| {
+            let user = user.trim();
+            user.eq_ignore_ascii_case(id) || user.eq_ignore_ascii_case(name)
+        };
+        if self.excluded_users.iter().any(matches) {
+            false
+        } else if self.users.is_empty() {
             // Always match if we have no users in the list.
             true
         } else {
-            // Match either the user ID or user name.
-            self.users.contains(&np.user_id) || self.users.contains(&np.user_name)
+            self.users.iter().any(matches)
         }
     }
 
-    pub async fn process(&mut self) {
-        while let Some(msg) = self.rx.recv().await {
-            match msg {
-                Message::NowPlaying(np) => {
-                    if !self.is_user_wanted(&np) {
-                        debug!(
-                            now_playing = ?np,
-                            users = ?self.users,
-                            "ignoring session from unwanted user"
-                        );
-                        break;
-                    }
-                    if let Err(e) = self.search_next(np).await {
-                        error!(err = ?e, "Failed to process");
-                    }
-                }
+    async fn now_playing(&self, np: NowPlaying) -> anyhow::Result<()> {
+        if let Some(session_log) = &self.session_log {
+            let seen_season = match np.kind {
+                MediaKind::Episode => np.season + 1,
+                MediaKind::Movie => np.season,
             };
+            let seen = self.seen().contains(&np.series, seen_season);
+            if let Err(e) = session_log.append(&np, seen).await {
+                warn!(err = ?e, "Failed to write session log");
+            }
+        }
+        if !self.is_user_wanted(&np) {
+            debug!(
+                now_playing = ?np,
+                users = ?self.users,
+                "ignoring session from unwanted user"
+            );
+            return Ok(());
+        }
+        // Sessions without a known progress are never held back.
+        if np.progress_pct.is_some_and(|p| p < self.min_progress) {
+            debug!(now_playing = ?np, "ignoring session below minimum progress");
+            return Ok(());
+        }
+        match np.kind {
+            MediaKind::Episode => {
+                let key = (np.series.clone(), np.season, np.episode);
+                if !self.dispatch(key.clone()) {
+                    debug!(now_playing = ?np, "skip already handled episode");
+                    return Ok(());
+                }
+                let result = self.search_next(np).await;
+                // only spare Sonarr once something was sent, try again next time otherwise
+                if !matches!(result, Ok(true)) {
+                    self.dispatched().remove(&key);
+                }
+                result.map(|_| ())
+            }
+            MediaKind::Movie => self.search_movie(np).await,
+        }
+    }
+
+    fn stopped(&self, np: &NowPlaying) {
+        let seen_season = match np.kind {
+            MediaKind::Episode => np.season + 1,
+            MediaKind::Movie => np.season,
+        };
+        self.dispatched()
+            .remove(&(np.series.clone(), np.season, np.episode));
+        if self.seen().forget(&np.series, seen_season) {
+            debug!(now_playing = ?np, "playback stopped early, search again on resume");
         }
     }
 
-    async fn search_next(&mut self, np: NowPlaying) -> anyhow::Result<()> {
-        let series = self.sonarr_client.series().await?;
-        let mut series = series
+    async fn search_movie(&self, np: NowPlaying) -> anyhow::Result<()> {
+        let Some(radarr_client) = &self.radarr_client else {
+            debug!(now_playing = ?np, "ignoring movie without Radarr");
+            return Ok(());
+        };
+
+        let movie = radarr_client
+            .movies()
+            .await?
             .into_iter()
-            .find(|s| match &np.series {
-                Series::Title(t) => s.title.as_ref() == Some(t),
-                Series::Tvdb(i) => &s.tvdb_id == i,
+            .find(|m| match &np.series {
+                Series::Title(t) => m.title.as_ref() == Some(t),
+                Series::Imdb(i) => m.imdb_id.as_ref() == Some(i),
+                Series::Tmdb(i) => &m.tmdb_id == i,
+                Series::Tvdb(_) => false,
+            })
+            .ok_or_else(|| anyhow!("movie not found in Radarr"))?;
+
+        info!(title = movie.title.clone().unwrap_or_else(|| "?".to_string()), now_playing = ?np);
+
+        if !self.seen().once(np.series.clone(), np.season, np.played_at) {
+            debug!(now_playing = ?np, "skip previously processed item");
+            return Ok(());
+        }
+
+        if movie.has_file {
+            debug!("skip already downloaded movie");
+            return Ok(());
+        }
+
+        info!("Searching movie");
+
+        // nothing was sent in a dry run
+        if radarr_client.search_movie(&movie).await?.is_null() {
+            return Ok(());
+        }
+
+        metrics::counter!(prometheus::MOVIES_SEARCHED).increment(1);
+
+        if let Some(notifier) = &self.notifier {
+            notifier
+                .movie_searched(movie.title.as_deref().unwrap_or_default())
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn find_series(&self, wanted: &Series) -> anyhow::Result<sonarr::SeriesResource> {
+        let mut series = self.sonarr_client.series().await?;
+        let found = match wanted {
+            Series::Title(t) => {
+                let slug = slug(t);
+                series
+                    .iter()
+                    .position(|s| s.title.as_ref() == Some(t))
+                    .or_else(|| {
+                        series
+                            .iter()
+                            .position(|s| s.title_slug.as_ref() == Some(&slug))
+                    })
+            }
+            Series::Tvdb(i) => series.iter().position(|s| &s.tvdb_id == i),
+            Series::Imdb(i) => series.iter().position(|s| s.imdb_id.as_ref() == Some(i)),
+            Series::Tmdb(i) => series.iter().position(|s| s.tmdb_id == Some(*i)),
+        };
+        if let Some(idx) = found {
+            return Ok(series.swap_remove(idx));
+        }
+
+        metrics::counter!(prometheus::SERIES_NOT_FOUND).increment(1);
+        if let Series::Title(title) = wanted {
+            self.suggest_series(title).await;
+        }
+        Err(anyhow!("series not found in Sonarr"))
+    }
+
+    /// Log series known to Sonarr's metadata provider with a similar title
+    async fn suggest_series(&self, title: &str) {
+        match self.sonarr_client.series_lookup(title).await {
+            Ok(results) if !results.is_empty() => {
+                let suggestions = results
+                    .iter()
+                    .take(5)
+                    .map(|s| match s.year {
+                        Some(year) => format!("{} ({year}, tvdb {})", s.title, s.tvdb_id),
+                        None => format!("{} (tvdb {})", s.title, s.tvdb_id),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                info!(title, "Series not in Sonarr, close matches: {suggestions}");
+            }
+            Ok(_) => debug!(title, "no close matches found"),
+            Err(e) => debug!("cannot look up series: {e}"),
+        }
+    }
+
+    /// Number of episodes of a season in the download queue
+    async fn queued_episodes(&self, series_id: i32, season_num: i32) -> i32 {
+        match self.sonarr_client.queue().await {
+            Ok(queue) => {
+                let episodes = queue
+                    .iter()
+                    .filter(|q| q.is_season(series_id, season_num))
+                    .filter_map(|q| q.episode_id)
+                    .collect::<HashSet<_>>();
+                i32::try_from(episodes.len()).unwrap_or(i32::MAX)
+            }
+            Err(e) => {
+                debug!("cannot list the download queue: {e}");
+                0
+            }
+        }
+    }
+
+    /// Numbers of the episodes of a season without a file
+    async fn missing_episodes(&self, series_id: i32, season_num: i32) -> Vec<i32> {
+        match self.sonarr_client.episodes(series_id, season_num).await {
+            Ok(episodes) => episodes
+                .iter()
+                .filter(|e| !e.has_file)
+                .map(|e| e.episode_number)
+                .collect(),
+            Err(e) => {
+                debug!("cannot list the episodes: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    fn is_series_wanted(&self, series: &sonarr::SeriesResource) -> bool {
+        if !self
+            .series_filter
+            .matches(series.title.as_deref().unwrap_or_default())
+        {
+            return false;
+        }
+        if self.series.is_empty() {
+            return true;
+        }
+        let tvdb_id = series.tvdb_id.to_string();
+        self.series.iter().any(|wanted| {
+            let wanted = wanted.trim();
+            wanted == tvdb_id || series.title.as_deref().is_some_and(|title| title == wanted)
+        })
+    }
+
+    fn has_required_quality_profile(&self, series: &sonarr::SeriesResource) -> bool {
+        self.required_quality_profile_ids.is_empty()
+            || series
+                .quality_profile_id
+                .is_some_and(|id| self.required_quality_profile_ids.contains(&id))
+    }
+
+    async fn has_required_tags(&self, series: &sonarr::SeriesResource) -> anyhow::Result<bool> {
+        if self.required_tags.is_empty() {
+            return Ok(true);
+        }
+        let series_tags = series.tags.as_deref().unwrap_or_default();
+        Ok(self
+            .sonarr_client
+            .tags()
+            .await?
+            .iter()
+            .filter(|tag| {
+                self.required_tags
+                    .iter()
+                    .any(|required| required.trim().eq_ignore_ascii_case(&tag.label))
             })
-            .ok_or_else(|| anyhow!("series not found in Sonarr"))?;
+            .any(|tag| series_tags.contains(&tag.id)))
+    }
+
+    /// Search the season after the playing episode if it is due, returning
+    /// whether a search or request was actually sent
+    async fn search_next(&self, np: NowPlaying) -> anyhow::Result<bool> {
+        let mut series = self.find_series(&np.series).await?;
+
+        if !self.is_series_wanted(&series) {
+            debug!(title = ?series.title, "skip series not in the list");
+            return Ok(false);
+        }
+
+        if !self.has_required_tags(&series).await? {
+            debug!(title = ?series.title, "skip series without required tags");
+            return Ok(false);
+        }
+
+        if !self.has_required_quality_profile(&series) {
+            debug!(title = ?series.title, profile = ?series.quality_profile_id, "skip series with other quality profile");
+            return Ok(false);
+        }
+
+        let profile_changed = self
+            .quality_profile_id
+            .is_some_and(|id| sonarr::Client::set_quality_profile(&mut series, id));
 
-        info!(title = series.title.clone().unwrap_or_else(|| "?".to_string()), now_playing = ?np);
+        let tvdb_id = series.tvdb_id;
+        info!(title = series.title.clone().unwrap_or_else(|| "?".to_string()), tvdb_id, now_playing = ?np);
 
         let season = series
             .season(np.season)
@@ -94,27 +566,43 @@ impl Actor {
 
         if !(is_end_of_season || is_pilot && is_only_episode) {
             debug!(now_playing = ?np, season = ?season, "ignoring early episode");
-            return Ok(());
+            return Ok(false);
         }
 
         let next_season = if is_pilot && is_only_episode {
-            info!("Stand-alone pilot episode detected, target first season");
+            info!(
+                tvdb_id,
+                "Stand-alone pilot episode detected, target first season"
+            );
             season
         } else if let Some(s) = series.season_mut(np.season + 1) {
             s
         } else {
-            info!("Next season not known, monitor new seasons instead");
+            if series.has_ended() {
+                info!(tvdb_id, "Series has ended and there is no next season");
+            } else {
+                info!(
+                    tvdb_id,
+                    "Next season not known, monitor new seasons instead"
+                );
+            }
             series.monitor_new_items = Some(sonarr::NewItemMonitorTypes::All);
             series.monitored = true;
             self.sonarr_client.put_series(&series).await?;
-            return Ok(());
+            return Ok(false);
         };
 
         let next_season_num = next_season.season_number;
+        let missing_episodes = next_season.statistics.as_ref().map_or(0, |s| {
+            s.total_episode_count.saturating_sub(s.episode_file_count)
+        });
 
-        if !self.seen.once(np.series.clone(), next_season_num) {
+        if !self
+            .seen()
+            .once(np.series.clone(), next_season_num, np.played_at)
+        {
             debug!(now_playing = ?np, "skip previously processed item");
-            return Ok(());
+            return Ok(false);
         }
 
         if let Some(statistics) = &next_season.statistics {
@@ -122,20 +610,161 @@ impl Actor {
                 && statistics.total_episode_count > 0
             {
                 debug!(num = next_season_num, "skip already downloaded season");
-                return Ok(());
+                return Ok(false);
             }
         }
 
-        info!(num = next_season_num, "Searching next season");
+        if missing_episodes > 0
+            && self.queued_episodes(series.id, next_season_num).await >= missing_episodes
+        {
+            debug!(
+                num = next_season_num,
+                "skip season with all missing episodes queued"
+            );
+            return Ok(false);
+        }
 
-        self.sonarr_client
-            .search_season(&series, next_season_num)
-            .await?;
+        if profile_changed {
+            info!(
+                tvdb_id,
+                id = self.quality_profile_id,
+                "Updating quality profile"
+            );
+            series.monitored = true;
+            if let Some(season) = series.season_mut(next_season_num) {
+                season.monitored = true;
+            }
+            self.sonarr_client.put_series(&series).await?;
+        }
 
-        Ok(())
+        info!(tvdb_id, num = next_season_num, "Searching next season");
+
+        let response = {
+            let permit = match &self.searches {
+                Some(searches) => Some(searches.acquire().await?),
+                None => None,
+            };
+            // Overseerr only knows series by their TMDB ID
+            match self.overseerr_client.as_ref().zip(series.tmdb_id) {
+                Some((overseerr_client, tmdb_id)) => {
+                    overseerr_client
+                        .request_tv(tmdb_id, series.tvdb_id, &[next_season_num])
+                        .await?
+                }
+                None => {
+                    let response = self
+                        .sonarr_client
+                        .search_season(&series, next_season_num)
+                        .await?;
+                    let command_id = response
+                        .get("id")
+                        .and_then(serde_json::Value::as_i64)
+                        .and_then(|id| i32::try_from(id).ok());
+                    // search_season already waited for it with a search timeout
+                    let must_wait =
+                        permit.is_some() && self.sonarr_client.search_timeout().is_zero();
+                    if let Some(command_id) = command_id.filter(|_| must_wait) {
+                        if let Err(e) = self
+                            .sonarr_client
+                            .wait_for_command(command_id, SEARCH_PERMIT_TIMEOUT)
+                            .await
+                        {
+                            debug!(num = next_season_num, "season search did not complete: {e}");
+                        }
+                    }
+                    response
+                }
+            }
+        };
+        // nothing was sent in a dry run or while the same search still runs
+        if response.is_null() {
+            return Ok(false);
+        }
+
+        if let Some(delay) = self.rescan_delay {
+            let sonarr_client = self.sonarr_client.clone();
+            let series_id = series.id;
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                if let Err(e) = sonarr_client.rescan_series(series_id).await {
+                    warn!(series = series_id, "Cannot rescan series: {e}");
+                }
+            });
+        }
+
+        if self.rename_after_download {
+            tokio::spawn(rename_when_downloaded(
+                self.sonarr_client.clone(),
+                series.id,
+                next_season_num,
+            ));
+        }
+
+        metrics::counter!(prometheus::SEASONS_SEARCHED).increment(1);
+        metrics::counter!(prometheus::EPISODES_SEARCHED)
+            .increment(u64::try_from(missing_episodes).unwrap_or(0));
+
+        if let Some(notifier) = &self.notifier {
+            let episodes = self.missing_episodes(series.id, next_season_num).await;
+            notifier
+                .season_searched(
+                    series.title.as_deref().unwrap_or_default(),
+                    next_season_num,
+                    &episodes,
+                )
+                .await;
+        }
+
+        Ok(true)
+    }
+}
+
+/// Rename the files of a series after downloads of a season showed up in the
+/// queue and left it again.
+async fn rename_when_downloaded(sonarr_client: sonarr::Client, series_id: i32, season_num: i32) {
+    let downloaded = async {
+        let mut was_queued = false;
+        loop {
+            tokio::time::sleep(QUEUE_POLL_INTERVAL).await;
+            match sonarr_client.queue().await {
+                Ok(queue) => {
+                    let queued = queue.iter().any(|q| q.is_season(series_id, season_num));
+                    if was_queued && !queued {
+                        break;
+                    }
+                    was_queued |= queued;
+                }
+                Err(e) => debug!("cannot list the download queue: {e}"),
+            }
+        }
+    };
+    if tokio::time::timeout(DOWNLOAD_TIMEOUT, downloaded)
+        .await
+        .is_err()
+    {
+        debug!(
+            series = series_id,
+            season = season_num,
+            "nothing downloaded to rename"
+        );
+        return;
+    }
+    if let Err(e) = sonarr_client.rename_series(series_id).await {
+        warn!(series = series_id, "Cannot rename series: {e}");
     }
 }
 
+/// Normalize a title the way Sonarr builds its title slugs.
+fn slug(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '-')
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric()).collect())
+        .filter(|word: &String| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
 #[cfg(test)]
 #[allow(clippy::too_many_lines)]
 mod test {
@@ -146,7 +775,7 @@ mod test {
     use tokio::sync::mpsc;
 
     use crate::{
-        media_server::{NowPlaying, Series},
+        media_server::{MediaKind, NowPlaying, Series},
         Message,
     };
 
@@ -239,7 +868,11 @@ mod test {
             .await;
 
         let (tx, rx) = mpsc::channel(1);
-        let sonarr = crate::sonarr::Client::new(&server.url("/pathprefix"), "secret")?;
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
         tokio::spawn(async move {
             super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
                 .process()
@@ -252,6 +885,11 @@ mod test {
             season: 1,
             user_id: "12345".to_string(),
             user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         }))
         .await?;
 
@@ -264,6 +902,106 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn notify_missing_episodes() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let _series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "TestShow",
+                    "tvdbId": 5678,
+                    "monitored": true,
+                    "seasons": [{
+                        "seasonNumber": 1,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 9000,
+                            "episodeCount": 3,
+                            "episodeFileCount": 3,
+                            "totalEpisodeCount": 3,
+                        }
+                    },{
+                        "seasonNumber": 2,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 3000,
+                            "episodeCount": 1,
+                            "episodeFileCount": 1,
+                            "totalEpisodeCount": 3,
+                        }
+                    }]
+                }]));
+            })
+            .await;
+
+        let _command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({ "id": 1 }));
+            })
+            .await;
+
+        let _episodes_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/episode")
+                    .query_param("seriesId", "1234")
+                    .query_param("seasonNumber", "2");
+                then.json_body(json!([
+                    { "episodeNumber": 1, "hasFile": true },
+                    { "episodeNumber": 2, "hasFile": false },
+                    { "episodeNumber": 3, "hasFile": false },
+                ]));
+            })
+            .await;
+
+        let notify_mock = server
+            .mock_async(|when, then| {
+                when.path("/notify").method(POST).json_body(json!({
+                    "series": "TestShow",
+                    "season": 2,
+                    "episodes": [2, 3],
+                }));
+                then.status(204);
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let notifier = crate::notify::Notifier::new(&[server.url("/notify")], &[])?;
+        tokio::spawn(
+            super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+                .with_notifier(notifier)
+                .process(),
+        );
+
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 3,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        notify_mock.assert_async().await;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn search_next_filter_users() -> Result<(), Box<dyn std::error::Error>> {
         let server = httpmock::MockServer::start_async().await;
@@ -353,7 +1091,11 @@ mod test {
             .await;
 
         let (tx, rx) = mpsc::channel(3);
-        let sonarr = crate::sonarr::Client::new(&server.url("/pathprefix"), "secret")?;
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
         tokio::spawn(async move {
             super::Actor::new(
                 rx,
@@ -373,15 +1115,39 @@ mod test {
             season: 1,
             user_id: "12345".to_string(),
             user_name: "other".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         }))
         .await?;
         // Valid username
         tx.send(Message::NowPlaying(NowPlaying {
             series: Series::Title("TestShow".to_string()),
-            episode: 7,
+            episode: 8,
             season: 1,
             user_id: "67890".to_string(),
             user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+        // Valid username, different case
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 6,
+            season: 1,
+            user_id: "67890".to_string(),
+            user_name: "TEST".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         }))
         .await?;
         // Invalid
@@ -391,14 +1157,19 @@ mod test {
             season: 1,
             user_id: "67890".to_string(),
             user_name: "unknown".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         }))
         .await?;
 
         tokio::time::sleep(Duration::from_millis(500)).await;
 
-        // We expect 2 requests to be made for the series search - one for the
-        // valid user ID and one for the valid user name.
-        series_mock.assert_hits_async(2).await;
+        // We expect 3 requests to be made for the series search - one for the
+        // valid user ID and one for each spelling of the valid user name.
+        series_mock.assert_hits_async(3).await;
         // But we only expect a single request to add the season and run a
         // search.
         put_series_mock.assert_async().await;
@@ -407,6 +1178,38 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn users_ignore_whitespace() -> Result<(), Box<dyn std::error::Error>> {
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            "http://localhost",
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(
+            rx,
+            sonarr,
+            crate::once::Seen::default(),
+            2,
+            vec![" Test ".to_string()],
+        );
+
+        assert!(actor.handler.is_user_wanted(&NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "67890".to_string(),
+            user_name: "test\n".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn search_next_skips_unwanted_users() -> Result<(), Box<dyn std::error::Error>> {
         let server = httpmock::MockServer::start_async().await;
@@ -427,7 +1230,11 @@ mod test {
             .await;
 
         let (tx, rx) = mpsc::channel(1);
-        let sonarr = crate::sonarr::Client::new(&server.url("/pathprefix"), "secret")?;
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
         tokio::spawn(async move {
             super::Actor::new(
                 rx,
@@ -446,6 +1253,11 @@ mod test {
             season: 40,
             user_id: "12345".to_string(),
             user_name: "unwanted".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         }))
         .await?;
 
@@ -516,7 +1328,11 @@ mod test {
             .await;
 
         let (tx, rx) = mpsc::channel(1);
-        let sonarr = crate::sonarr::Client::new(&server.url("/pathprefix"), "secret")?;
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
         tokio::spawn(async move {
             super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
                 .process()
@@ -529,6 +1345,11 @@ mod test {
             season: 1,
             user_id: "12345".to_string(),
             user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         }))
         .await?;
 
@@ -611,7 +1432,11 @@ mod test {
             .await;
 
         let (tx, rx) = mpsc::channel(1);
-        let sonarr = crate::sonarr::Client::new(&server.url("/pathprefix"), "secret")?;
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
         tokio::spawn(async move {
             super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
                 .process()
@@ -624,6 +1449,11 @@ mod test {
             season: 1,
             user_id: "12345".to_string(),
             user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
         }))
         .await?;
 
@@ -635,4 +1465,1111 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn quality_profile() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(serde_json::json!(
+                    [{
+                            "id": 1234,
+                            "title": "TestShow",
+                            "tvdbId": 5678,
+                            "monitored": true,
+                            "monitorNewItems": "all",
+                            "qualityProfileId": 1,
+                            "seasons": [{
+                                "seasonNumber": 1,
+                                "monitored": true,
+                                "statistics": {
+                                    "sizeOnDisk": 9000,
+                                    "episodeCount": 8,
+                                    "episodeFileCount": 8,
+                                    "totalEpisodeCount": 8,
+                                }
+                            },{
+                                "seasonNumber": 2,
+                                "monitored": true,
+                                "statistics": {
+                                    "sizeOnDisk": 9000,
+                                    "episodeCount": 0,
+                                    "episodeFileCount": 0,
+                                    "totalEpisodeCount": 8,
+                                }
+                            }]
+                        }
+                    ]
+                ));
+            })
+            .await;
+
+        let put_series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234")
+                    .method(PUT)
+                    .json_body(serde_json::json!(
+                        {
+                            "id": 1234,
+                            "title": "TestShow",
+                            "tvdbId": 5678,
+                            "monitored": true,
+                            "monitorNewItems": "all",
+                            "qualityProfileId": 7,
+                            "seasons": [{
+                                "seasonNumber": 1,
+                                "monitored": true,
+                                "statistics": {
+                                    "sizeOnDisk": 9000,
+                                    "episodeCount": 8,
+                                    "episodeFileCount": 8,
+                                    "totalEpisodeCount": 8,
+                                }
+                            },{
+                                "seasonNumber": 2,
+                                "monitored": true,
+                                "statistics": {
+                                    "sizeOnDisk": 9000,
+                                    "episodeCount": 0,
+                                    "episodeFileCount": 0,
+                                    "totalEpisodeCount": 8,
+                                }
+                            }]
+                        }
+                    ));
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command")
+                    .method(POST)
+                    .json_body(json!({
+                        "name": "SeasonSearch",
+                        "seriesId": 1234,
+                        "seasonNumber": 2,
+                    }));
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        tokio::spawn(async move {
+            super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+                .with_quality_profile(7)
+                .process()
+                .await;
+        });
+
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        series_mock.assert_async().await;
+        put_series_mock.assert_async().await;
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(serde_json::json!([]));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::Shutdown).await?;
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![]);
+
+        // returns although `tx` is still around
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        // the queued message got processed anyway
+        series_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.delay(Duration::from_secs(60))
+                    .json_body(serde_json::json!([]));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_shutdown_timeout(Duration::from_millis(100));
+
+        // the hanging request is abandoned
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        series_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn movie() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/sonarr/api/v3/series");
+                then.json_body(serde_json::json!([]));
+            })
+            .await;
+
+        let movies_mock = server
+            .mock_async(|when, then| {
+                when.path("/radarr/api/v3/movie");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "Test Movie",
+                    "tmdbId": 5678,
+                    "monitored": true,
+                    "hasFile": false,
+                }]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/radarr/api/v3/command")
+                    .method(POST)
+                    .json_body(json!({
+                        "name": "MoviesSearch",
+                        "movieIds": [1234],
+                    }));
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("Test Movie".to_string()),
+            episode: 0,
+            season: 0,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Movie,
+        }))
+        .await?;
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/sonarr"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let radarr = crate::radarr::Client::new(
+            &server.url("/radarr"),
+            "secret",
+            &crate::radarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_radarr(radarr);
+
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        series_mock.assert_hits_async(0).await;
+        movies_mock.assert_async().await;
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn required_tags() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "TestShow",
+                    "tvdbId": 5678,
+                    "monitored": true,
+                    "tags": [1],
+                    "seasons": [{
+                        "seasonNumber": 1,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 9000,
+                            "episodeCount": 8,
+                            "episodeFileCount": 8,
+                            "totalEpisodeCount": 8,
+                        }
+                    },{
+                        "seasonNumber": 2,
+                        "monitored": true,
+                    }]
+                }]));
+            })
+            .await;
+
+        let tags_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/tag");
+                then.json_body(json!([
+                    { "id": 1, "label": "anime" },
+                    { "id": 2, "label": "tv" },
+                ]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_required_tags(vec!["TV".to_string()]);
+
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        series_mock.assert_async().await;
+        tags_mock.assert_async().await;
+        command_mock.assert_hits_async(0).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn min_progress() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([]));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: Some(2.5),
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_min_progress(10.0);
+
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        series_mock.assert_hits_async(0).await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn series_filter() -> Result<(), Box<dyn std::error::Error>> {
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            "http://localhost",
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_series(vec!["TestShow".to_string(), " 5678 ".to_string()]);
+
+        let series = |title: &str, tvdb_id: i32| {
+            serde_json::from_value::<crate::sonarr::SeriesResource>(json!({
+                "id": 1234,
+                "title": title,
+                "tvdbId": tvdb_id,
+                "monitored": true,
+                "seasons": [],
+            }))
+        };
+        assert!(actor.handler.is_series_wanted(&series("TestShow", 1)?));
+        assert!(actor.handler.is_series_wanted(&series("Other", 5678)?));
+        assert!(!actor.handler.is_series_wanted(&series("testshow", 1)?));
+
+        let actor =
+            actor.with_series_filter(crate::filter::SeriesFilter::new(vec![regex::Regex::new(
+                "^Test",
+            )?]));
+        assert!(actor.handler.is_series_wanted(&series("TestShow", 1)?));
+        assert!(!actor.handler.is_series_wanted(&series("Other", 5678)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn excluded_users() -> Result<(), Box<dyn std::error::Error>> {
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            "http://localhost",
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_excluded_users(vec![" Guest ".to_string()]);
+
+        let np = |user_name: &str| NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "67890".to_string(),
+            user_name: user_name.to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        };
+        assert!(!actor.handler.is_user_wanted(&np("guest")));
+        assert!(actor.handler.is_user_wanted(&np("owner")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ended_series() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "TestShow",
+                    "tvdbId": 5678,
+                    "status": "ended",
+                    "monitored": true,
+                    "seasons": [{
+                        "seasonNumber": 1,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 9000,
+                            "episodeCount": 8,
+                            "episodeFileCount": 8,
+                            "totalEpisodeCount": 8,
+                        }
+                    }]
+                }]));
+            })
+            .await;
+
+        let put_series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234").method(PUT);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![]);
+
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        series_mock.assert_async().await;
+        put_series_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn overseerr() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "TestShow",
+                    "tvdbId": 5678,
+                    "tmdbId": 4321,
+                    "monitored": true,
+                    "seasons": [{
+                        "seasonNumber": 1,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 9000,
+                            "episodeCount": 8,
+                            "episodeFileCount": 8,
+                            "totalEpisodeCount": 8,
+                        }
+                    },{
+                        "seasonNumber": 2,
+                        "monitored": false,
+                    }]
+                }]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let request_mock = server
+            .mock_async(|when, then| {
+                when.path("/overseerr/api/v1/request")
+                    .method(POST)
+                    .json_body(json!({
+                        "mediaType": "tv",
+                        "mediaId": 4321,
+                        "tvdbId": 5678,
+                        "seasons": [2],
+                    }));
+                then.status(201).json_body(json!({}));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Title("TestShow".to_string()),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let overseerr = crate::overseerr::Client::new(
+            &server.url("/overseerr"),
+            "secret",
+            &crate::overseerr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_overseerr(overseerr);
+
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        series_mock.assert_async().await;
+        request_mock.assert_async().await;
+        command_mock.assert_hits_async(0).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_searches() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series = |id: i32, title: &str| {
+            json!({
+                "id": id,
+                "title": title,
+                "tvdbId": id,
+                "monitored": true,
+                "seasons": [{
+                    "seasonNumber": 1,
+                    "monitored": true,
+                    "statistics": {
+                        "sizeOnDisk": 9000,
+                        "episodeCount": 8,
+                        "episodeFileCount": 8,
+                        "totalEpisodeCount": 8,
+                    }
+                },{
+                    "seasonNumber": 2,
+                    "monitored": true,
+                }]
+            })
+        };
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([series(1, "ShowA"), series(2, "ShowB")]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({ "id": 1 }));
+            })
+            .await;
+
+        let status_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command/1");
+                then.json_body(json!({ "name": "SeasonSearch", "status": "completed" }))
+                    .delay(Duration::from_millis(200));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(3);
+        for title in ["ShowA", "ShowB"] {
+            tx.send(Message::NowPlaying(NowPlaying {
+                series: Series::Title(title.to_string()),
+                episode: 7,
+                season: 1,
+                user_id: "12345".to_string(),
+                user_name: "test".to_string(),
+                is_paused: false,
+                position_ticks: None,
+                progress_pct: None,
+                played_at: None,
+                kind: MediaKind::Episode,
+            }))
+            .await?;
+        }
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_max_concurrent_searches(1);
+
+        let start = std::time::Instant::now();
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        series_mock.assert_hits_async(2).await;
+        command_mock.assert_hits_async(2).await;
+        status_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn required_quality_profile() -> Result<(), Box<dyn std::error::Error>> {
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            "http://localhost",
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![])
+            .with_required_quality_profile_ids(vec![1, 2]);
+
+        let series = |quality_profile_id: Option<i32>| {
+            serde_json::from_value::<crate::sonarr::SeriesResource>(json!({
+                "id": 1234,
+                "title": "TestShow",
+                "tvdbId": 5678,
+                "monitored": true,
+                "qualityProfileId": quality_profile_id,
+                "seasons": [],
+            }))
+        };
+        assert!(actor
+            .handler
+            .has_required_quality_profile(&series(Some(2))?));
+        assert!(!actor
+            .handler
+            .has_required_quality_profile(&series(Some(4))?));
+        assert!(!actor.handler.has_required_quality_profile(&series(None)?));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn skip_dispatched_episode() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "TestShow",
+                    "tvdbId": 5678,
+                    "monitored": true,
+                    "seasons": [{
+                        "seasonNumber": 1,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 9000,
+                            "episodeCount": 8,
+                            "episodeFileCount": 8,
+                            "totalEpisodeCount": 8,
+                        }
+                    },{
+                        "seasonNumber": 2,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 0,
+                            "episodeCount": 0,
+                            "episodeFileCount": 0,
+                            "totalEpisodeCount": 8,
+                        }
+                    }]
+                }]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![]);
+
+        let np = NowPlaying {
+            series: Series::Tvdb(5678),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        };
+        // searched, so Sonarr is spared the second time
+        actor.handler.now_playing(np.clone()).await?;
+        actor.handler.now_playing(np.clone()).await?;
+        series_mock.assert_hits_async(1).await;
+        command_mock.assert_hits_async(1).await;
+
+        // nothing was sent for an early episode, so it is looked at again
+        let early = NowPlaying { episode: 3, ..np };
+        actor.handler.now_playing(early.clone()).await?;
+        actor.handler.now_playing(early).await?;
+        series_mock.assert_hits_async(3).await;
+        assert_eq!(actor.handler.dispatched().len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dispatch_expires() -> Result<(), Box<dyn std::error::Error>> {
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            "http://localhost",
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(
+            rx,
+            sonarr,
+            crate::once::Seen::new(Duration::from_secs(60)),
+            2,
+            vec![],
+        );
+
+        let key = (Series::Tvdb(5678), 1, 3);
+        assert!(actor.handler.dispatch(key.clone()));
+        assert!(!actor.handler.dispatch(key.clone()));
+
+        let expired = std::time::Instant::now()
+            .checked_sub(Duration::from_secs(61))
+            .ok_or("no instant that early")?;
+        actor.handler.dispatched().insert(key.clone(), expired);
+        assert!(actor.handler.dispatch(key));
+        assert_eq!(actor.handler.dispatched().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queued_season() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "TestShow",
+                    "tvdbId": 5678,
+                    "monitored": true,
+                    "seasons": [{
+                        "seasonNumber": 1,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 9000,
+                            "episodeCount": 8,
+                            "episodeFileCount": 8,
+                            "totalEpisodeCount": 8,
+                        }
+                    },{
+                        "seasonNumber": 2,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 1000,
+                            "episodeCount": 2,
+                            "episodeFileCount": 0,
+                            "totalEpisodeCount": 2,
+                        }
+                    }]
+                }]));
+            })
+            .await;
+
+        let queue_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/queue/details");
+                then.json_body(json!([
+                    { "seriesId": 1234, "episodeId": 21, "seasonNumber": 2, "status": "downloading" },
+                    { "seriesId": 1234, "episodeId": 22, "seasonNumber": 2, "status": "queued" },
+                ]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(Message::NowPlaying(NowPlaying {
+            series: Series::Tvdb(5678),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        }))
+        .await?;
+        tx.send(Message::Shutdown).await?;
+
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![]);
+
+        tokio::time::timeout(Duration::from_secs(5), actor.process()).await?;
+
+        series_mock.assert_async().await;
+        queue_mock.assert_async().await;
+        command_mock.assert_hits_async(0).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stopped() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "TestShow",
+                    "tvdbId": 5678,
+                    "monitored": true,
+                    "seasons": [{
+                        "seasonNumber": 1,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 9000,
+                            "episodeCount": 8,
+                            "episodeFileCount": 8,
+                            "totalEpisodeCount": 8,
+                        }
+                    },{
+                        "seasonNumber": 2,
+                        "monitored": true,
+                        "statistics": {
+                            "sizeOnDisk": 1000,
+                            "episodeCount": 2,
+                            "episodeFileCount": 0,
+                            "totalEpisodeCount": 2,
+                        }
+                    }]
+                }]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let (tx, rx) = mpsc::channel(4);
+        let np = NowPlaying {
+            series: Series::Tvdb(5678),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        };
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![]);
+        let process = tokio::spawn(actor.process());
+
+        tx.send(Message::NowPlaying(np.clone())).await?;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while command_mock.hits_async().await == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        tx.send(Message::Stopped(np.clone())).await?;
+        tx.send(Message::NowPlaying(np)).await?;
+        tx.send(Message::Shutdown).await?;
+
+        tokio::time::timeout(Duration::from_secs(5), process).await??;
+
+        series_mock.assert_hits_async(2).await;
+        command_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn slug() {
+        assert_eq!(
+            super::slug("Marvel's Agents of S.H.I.E.L.D."),
+            "marvels-agents-of-shield"
+        );
+        assert_eq!(super::slug("  Star Trek - Picard "), "star-trek-picard");
+    }
+
+    #[tokio::test]
+    async fn find_series_by_slug() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let _series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(serde_json::json!(
+                    [{
+                        "id": 1234,
+                        "title": "Marvel's Agents of S.H.I.E.L.D",
+                        "titleSlug": "marvels-agents-of-shield",
+                        "tvdbId": 5678,
+                        "monitored": false,
+                        "seasons": []
+                    }]
+                ));
+            })
+            .await;
+
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![]);
+
+        let series = actor
+            .handler
+            .find_series(&Series::Title(
+                "Marvel's Agents of S.H.I.E.L.D.".to_string(),
+            ))
+            .await?;
+        assert_eq!(series.id, 1234);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn find_series_by_imdb() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let _series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(serde_json::json!(
+                    [{
+                        "id": 1234,
+                        "title": "TestShow",
+                        "tvdbId": 5678,
+                        "monitored": false,
+                        "seasons": []
+                    },{
+                        "id": 4321,
+                        "title": "OtherShow",
+                        "tvdbId": 8765,
+                        "imdbId": "tt0123456",
+                        "monitored": false,
+                        "seasons": []
+                    }]
+                ));
+            })
+            .await;
+
+        let (_tx, rx) = mpsc::channel(1);
+        let sonarr = crate::sonarr::Client::new(
+            &server.url("/pathprefix"),
+            "secret",
+            &crate::sonarr::Options::default(),
+        )?;
+        let actor = super::Actor::new(rx, sonarr, crate::once::Seen::default(), 2, vec![]);
+
+        let series = actor
+            .handler
+            .find_series(&Series::Imdb("tt0123456".to_string()))
+            .await?;
+        assert_eq!(series.id, 4321);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,88 @@
+use std::future::Future;
+
+use anyhow::{Context as _, Result};
+use axum::{extract::State, routing::get, Router};
+use metrics::{describe_counter, describe_gauge};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tokio::net::TcpListener;
+
+pub const EPISODES_SEARCHED: &str = "prefetcharr_episodes_searched_total";
+pub const SEASONS_SEARCHED: &str = "prefetcharr_seasons_searched_total";
+pub const MOVIES_SEARCHED: &str = "prefetcharr_movies_searched_total";
+pub const SERIES_NOT_FOUND: &str = "prefetcharr_series_not_found_total";
+pub const POLL_ERRORS: &str = "prefetcharr_media_server_poll_errors_total";
+pub const SEEN_CACHE_SIZE: &str = "prefetcharr_seen_cache_size";
+
+/// Record metrics from now on and return a handle to render them
+pub fn install() -> Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new().install_recorder()?;
+    describe();
+    Ok(handle)
+}
+
+fn describe() {
+    describe_counter!(
+        EPISODES_SEARCHED,
+        "Missing episodes covered by season searches"
+    );
+    describe_counter!(SEASONS_SEARCHED, "Season searches sent to Sonarr");
+    describe_counter!(MOVIES_SEARCHED, "Movie searches sent to Radarr");
+    describe_counter!(SERIES_NOT_FOUND, "Sessions of series unknown to Sonarr");
+    describe_counter!(POLL_ERRORS, "Failed attempts to fetch sessions");
+    describe_gauge!(SEEN_CACHE_SIZE, "Seasons remembered as searched");
+}
+
+/// Serve the metrics in Prometheus text format on `GET /metrics`.
+///
+/// The port is bound right away, so a port in use is noticed at startup.
+/// Requests are served by the returned future.
+pub async fn listen(
+    port: u16,
+    handle: PrometheusHandle,
+) -> Result<impl Future<Output = Result<()>>> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .with_context(|| format!("Cannot listen for metrics requests on port {port}"))?;
+    Ok(serve(listener, handle))
+}
+
+async fn serve(listener: TcpListener, handle: PrometheusHandle) -> Result<()> {
+    let app = Router::new()
+        .route(
+            "/metrics",
+            get(|State(handle): State<PrometheusHandle>| async move { handle.render() }),
+        )
+        .with_state(handle);
+    axum::serve(listener, app)
+        .await
+        .context("Metrics server failed")
+}
+
+#[cfg(test)]
+mod test {
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn render() -> Result<(), Box<dyn std::error::Error>> {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            super::describe();
+            metrics::counter!(super::SEASONS_SEARCHED).increment(1);
+            metrics::gauge!(super::SEEN_CACHE_SIZE).set(3.0);
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/metrics", listener.local_addr()?);
+        let server = tokio::spawn(super::serve(listener, handle));
+
+        let body = reqwest::get(&url).await?.error_for_status()?.text().await?;
+        assert!(body.contains("# HELP prefetcharr_seasons_searched_total"));
+        assert!(body.contains("prefetcharr_seasons_searched_total 1"));
+        assert!(body.contains("prefetcharr_seen_cache_size 3"));
+
+        server.abort();
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Url,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Clone)]
+pub struct Client {
+    base_url: Url,
+    client: reqwest::Client,
+    dry_run: bool,
+}
+
+/// Optional connection settings
+#[derive(Default)]
+pub struct Options {
+    /// Only log requests that would change something in Radarr
+    pub dry_run: bool,
+    /// Trust only the certificate authorities in this PEM file
+    pub ca_cert: Option<PathBuf>,
+    /// Give up on requests that take longer than this
+    pub request_timeout: Option<Duration>,
+    /// Give up on connecting after this long
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Client {
+    pub fn new(base_url: &str, api_key: &str, options: &Options) -> Result<Self> {
+        let mut api_key = HeaderValue::from_str(api_key)?;
+        api_key.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Api-Key", api_key);
+        headers.insert(
+            reqwest::header::ACCEPT,
+            HeaderValue::from_static("application/json"),
+        );
+
+        let client = crate::tls::client_builder(
+            headers,
+            options.ca_cert.as_deref(),
+            options.request_timeout,
+            options.connect_timeout,
+        )?
+        .build()?;
+
+        let base_url = crate::http::base_url(base_url)?;
+
+        Ok(Self {
+            base_url,
+            client,
+            dry_run: options.dry_run,
+        })
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        crate::http::api_url(&self.base_url, &["api", "v3"], path)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self
+            .client
+            .get(self.url(path)?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json::<T>().await?)
+    }
+
+    pub async fn probe(&self) -> Result<()> {
+        let url = crate::http::api_url(&self.base_url, &[], "api")?;
+        self.client.get(url).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn movies(&self) -> Result<Vec<MovieResource>> {
+        self.get("movie").await
+    }
+
+    pub async fn put_movie(&self, movie: &MovieResource) -> Result<Value> {
+        let url = self.url(&format!("movie/{}", movie.id))?;
+        if crate::http::is_dry_run(self.dry_run, "PUT", &url, movie) {
+            return Ok(Value::Null);
+        }
+        let response = self
+            .client
+            .put(url)
+            .json(movie)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn search_movie(&self, movie: &MovieResource) -> Result<Value> {
+        if !movie.monitored {
+            let mut movie = movie.clone();
+            movie.monitored = true;
+            self.put_movie(&movie).await?;
+        }
+
+        let cmd = json!({
+            "name": "MoviesSearch",
+            "movieIds": [movie.id],
+        });
+
+        let url = self.url("command")?;
+        if crate::http::is_dry_run(self.dry_run, "POST", &url, &cmd) {
+            return Ok(Value::Null);
+        }
+        let response = self
+            .client
+            .post(url)
+            .json(&cmd)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json().await?)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MovieResource {
+    pub id: i32,
+    pub title: Option<String>,
+    pub tmdb_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imdb_id: Option<String>,
+    pub monitored: bool,
+    #[serde(default)]
+    pub has_file: bool,
+    #[serde(flatten)]
+    other: serde_json::Value,
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::Method::{POST, PUT};
+    use serde_json::json;
+
+    use crate::radarr::Options;
+
+    #[test]
+    fn ca_cert() {
+        let options = Options {
+            ca_cert: Some("/nonexistent/ca.pem".into()),
+            ..Options::default()
+        };
+        assert!(super::Client::new("http://localhost", "secret", &options).is_err());
+    }
+
+    #[tokio::test]
+    async fn movies() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let movies_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/movie")
+                    .header("X-Api-Key", "secret");
+                then.json_body(json!([{
+                    "id": 1234,
+                    "title": "Test Movie",
+                    "tmdbId": 5678,
+                    "imdbId": "tt0123456",
+                    "monitored": true,
+                    "hasFile": false,
+                    "year": 2024,
+                }]));
+            })
+            .await;
+        let client =
+            super::Client::new(&server.url("/pathprefix/"), "secret", &Options::default())?;
+
+        let movies = client.movies().await?;
+
+        movies_mock.assert_async().await;
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].id, 1234);
+        assert_eq!(movies[0].title.as_deref(), Some("Test Movie"));
+        assert_eq!(movies[0].tmdb_id, 5678);
+        assert_eq!(movies[0].imdb_id.as_deref(), Some("tt0123456"));
+        assert!(!movies[0].has_file);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_movie() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let put_movie_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/movie/1234")
+                    .method(PUT)
+                    .json_body(json!({
+                        "id": 1234,
+                        "title": "Test Movie",
+                        "tmdbId": 5678,
+                        "monitored": true,
+                        "hasFile": false,
+                        "year": 2024,
+                    }));
+                then.json_body(json!({}));
+            })
+            .await;
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command")
+                    .method(POST)
+                    .json_body(json!({
+                        "name": "MoviesSearch",
+                        "movieIds": [1234],
+                    }));
+                then.json_body(json!({}));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let movie = serde_json::from_value(json!({
+            "id": 1234,
+            "title": "Test Movie",
+            "tmdbId": 5678,
+            "monitored": false,
+            "hasFile": false,
+            "year": 2024,
+        }))?;
+        client.search_movie(&movie).await?;
+
+        put_movie_mock.assert_async().await;
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+}
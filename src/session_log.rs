@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt as _;
+
+use crate::media_server::{NowPlaying, Series};
+
+/// Appends every incoming session as a JSON line to a file.
+pub struct SessionLog {
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    user_id: &'a str,
+    user_name: &'a str,
+    series: &'a Series,
+    season: i32,
+    episode: i32,
+    /// The following season was processed before
+    seen: bool,
+}
+
+impl SessionLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub async fn append(&self, np: &NowPlaying, seen: bool) -> Result<()> {
+        let record = Record {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            user_id: &np.user_id,
+            user_name: &np.user_name,
+            series: &np.series,
+            season: np.season,
+            episode: np.episode,
+            seen,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+
+    use crate::{
+        media_server::{MediaKind, NowPlaying, Series},
+        session_log::SessionLog,
+    };
+
+    #[tokio::test]
+    async fn append() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sessions.jsonl");
+
+        let log = SessionLog::new(path.clone());
+        let np = NowPlaying {
+            series: Series::Tvdb(1234),
+            episode: 7,
+            season: 1,
+            user_id: "12345".to_string(),
+            user_name: "test".to_string(),
+            is_paused: false,
+            position_ticks: None,
+            progress_pct: None,
+            played_at: None,
+            kind: MediaKind::Episode,
+        };
+        log.append(&np, false).await?;
+        log.append(&np, true).await?;
+
+        let content = std::fs::read_to_string(&path)?;
+        let records = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?;
+        assert_eq!(records.len(), 2);
+        for (record, seen) in records.iter().zip([false, true]) {
+            assert!(record["timestamp"].is_string());
+            assert_eq!(record["user_id"], json!("12345"));
+            assert_eq!(record["user_name"], json!("test"));
+            assert_eq!(record["series"], json!({ "Tvdb": 1234 }));
+            assert_eq!(record["season"], json!(1));
+            assert_eq!(record["episode"], json!(7));
+            assert_eq!(record["seen"], json!(seen));
+        }
+
+        Ok(())
+    }
+}
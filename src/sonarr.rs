@@ -1,19 +1,104 @@
-use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
-    Url,
+    Method, Response, StatusCode, Url,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::{json, Value};
-use tracing::debug;
+use serde_json::{json, Map, Value};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{debug, info, trace, warn};
 
+#[derive(Clone)]
 pub struct Client {
     base_url: Url,
     client: reqwest::Client,
+    dry_run: bool,
+    search_timeout: Duration,
+    version: SonarrVersion,
+    rate_limit: Option<Arc<RateLimit>>,
+    request_retries: u32,
+    retry_delay: Duration,
+    max_retry_delay: Option<Duration>,
+    /// Set once patching a season failed, shared by all clones of a client
+    patch_unsupported: Arc<AtomicBool>,
+}
+
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// API generation spoken by the Sonarr instance
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SonarrVersion {
+    /// Sonarr v2, serving its API below `/api/`
+    V2,
+    /// Sonarr v3 and later, serving its API below `/api/v3/`
+    #[default]
+    V3,
+}
+
+/// Spaces out requests, shared by all clones of a client
+struct RateLimit {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn new(per_second: f64) -> Result<Self> {
+        let interval = Duration::try_from_secs_f64(per_second.recip())
+            .map_err(|_| anyhow!("invalid rate limit of {per_second} requests per second"))?;
+        Ok(Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        })
+    }
+
+    async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        tokio::time::sleep_until(*next).await;
+        *next = Instant::now() + self.interval;
+    }
+}
+
+/// Optional connection settings
+#[derive(Default)]
+pub struct Options {
+    /// Cookies to send along with every request, e.g. `cf_clearance`
+    pub cookies: HashMap<String, String>,
+    /// Only log requests that would change something in Sonarr
+    pub dry_run: bool,
+    /// Wait this long for searches to complete, zero to not wait at all
+    pub search_timeout: Duration,
+    /// Send at most this many requests per second, no limit if unset
+    pub rate_limit: Option<f64>,
+    /// Repeat requests this often while Sonarr is unavailable
+    pub request_retries: u32,
+    /// Wait this long before the first retry, doubling with every further one
+    pub retry_delay: Duration,
+    /// Wait at most this long between retries, no limit if unset
+    pub max_retry_delay: Option<Duration>,
+    /// Trust only the certificate authorities in this PEM file
+    pub ca_cert: Option<PathBuf>,
+    /// Authenticate with this client certificate and private key, both PEM files
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+    /// Keep at most this many unused connections open, no limit if unset
+    pub max_idle_connections: Option<usize>,
+    /// Give up on requests that take longer than this
+    pub request_timeout: Option<Duration>,
+    /// Give up on connecting after this long
+    pub connect_timeout: Option<Duration>,
 }
 
 impl Client {
-    pub fn new(base_url: &str, api_key: &str) -> Result<Self> {
+    pub fn new(base_url: &str, api_key: &str, options: &Options) -> Result<Self> {
         let mut api_key = HeaderValue::from_str(api_key)?;
         api_key.set_sensitive(true);
         let mut headers = HeaderMap::new();
@@ -23,59 +108,214 @@ impl Client {
             HeaderValue::from_static("application/json"),
         );
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()?;
+        if !options.cookies.is_empty() {
+            let cookies = options
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>()
+                .join("; ");
+            let mut cookies = HeaderValue::from_str(&cookies)?;
+            cookies.set_sensitive(true);
+            headers.insert(reqwest::header::COOKIE, cookies);
+        }
+
+        let mut client = crate::tls::client_builder(
+            headers,
+            options.ca_cert.as_deref(),
+            options.request_timeout,
+            options.connect_timeout,
+        )?;
+        if let Some((cert, key)) = &options.client_cert {
+            client = crate::tls::client_auth(client, cert, key)?;
+        }
+        if let Some(max) = options.max_idle_connections {
+            client = client.pool_max_idle_per_host(max);
+        }
+        let client = client.build()?;
+
+        let base_url = crate::http::base_url(base_url)?;
+
+        Ok(Self {
+            base_url,
+            client,
+            dry_run: options.dry_run,
+            search_timeout: options.search_timeout,
+            version: SonarrVersion::default(),
+            rate_limit: options
+                .rate_limit
+                .map(RateLimit::new)
+                .transpose()?
+                .map(Arc::new),
+            request_retries: options.request_retries,
+            retry_delay: options.retry_delay,
+            max_retry_delay: options.max_retry_delay,
+            patch_unsupported: Arc::default(),
+        })
+    }
+
+    /// How long searches are waited for, zero if not at all
+    pub fn search_timeout(&self) -> Duration {
+        self.search_timeout
+    }
 
-        let base_url = base_url.parse()?;
+    async fn throttle(&self) {
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.wait().await;
+        }
+    }
 
-        Ok(Self { base_url, client })
+    fn url(&self, path: &str) -> Result<Url> {
+        let prefix: &[&str] = match self.version {
+            SonarrVersion::V3 => &["api", "v3"],
+            SonarrVersion::V2 => &["api"],
+        };
+        crate::http::api_url(&self.base_url, prefix, path)
     }
 
     async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
-        let mut url = self.base_url.clone();
-        url.path_segments_mut()
-            .map_err(|()| anyhow!("url is relative"))?
-            .push("api")
-            .push("v3")
-            .extend(path.split('/'));
-        let response = self.client.get(url).send().await?.error_for_status()?;
+        self.get_query(path, &[]).await
+    }
+
+    async fn get_query<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        let url = self.url(path)?;
+        trace!(%url, ?query, "GET");
+        let response = self
+            .send_with_retries(self.client.get(url).query(query))
+            .await?;
         Ok(response.json::<T>().await?)
     }
 
-    pub async fn probe(&self) -> Result<()> {
-        let mut url = self.base_url.clone();
-        url.path_segments_mut()
-            .map_err(|()| anyhow!("url is relative"))?
-            .push("api");
-        self.client.get(url).send().await?.error_for_status()?;
+    /// Check the connection and detect the API version to use from now on
+    pub async fn probe(&mut self) -> Result<()> {
+        let url = crate::http::api_url(&self.base_url, &[], "api")?;
+        self.throttle().await;
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        // v3 and later answer with e.g. `{"current": "v3"}`, v2 does not
+        let is_versioned = response
+            .json::<Value>()
+            .await
+            .is_ok_and(|api| api.get("current").is_some());
+        if !is_versioned && self.is_v2().await {
+            info!("Sonarr v2 detected, using its legacy API");
+            self.version = SonarrVersion::V2;
+        }
         Ok(())
     }
 
+    /// Ask the unversioned status endpoint that only exists up to v2
+    async fn is_v2(&self) -> bool {
+        let legacy = Self {
+            version: SonarrVersion::V2,
+            ..self.clone()
+        };
+        let version = match legacy.get::<Value>("system/status").await {
+            Ok(status) => status
+                .get("version")
+                .and_then(Value::as_str)
+                .map(ToString::to_string),
+            Err(e) => {
+                debug!("cannot get the Sonarr version: {e}");
+                None
+            }
+        };
+        version
+            .and_then(|v| v.split('.').next()?.parse::<u32>().ok())
+            .is_some_and(|major| major < 3)
+    }
+
     pub async fn put_series(&self, series: &SeriesResource) -> Result<serde_json::Value> {
-        let mut url = self.base_url.clone();
-        url.path_segments_mut()
-            .map_err(|()| anyhow!("url is relative"))?
-            .push("api")
-            .push("v3")
-            .push("series")
-            .push(&series.id.to_string());
+        let url = self.url(&format!("series/{}", series.id))?;
+        trace_body("PUT", &url, series);
+        if crate::http::is_dry_run(self.dry_run, "PUT", &url, series) {
+            return Ok(Value::Null);
+        }
         let response = self
-            .client
-            .put(url)
-            .json(series)
-            .send()
-            .await?
-            .error_for_status()?;
+            .send_with_retries(self.client.put(url).json(series))
+            .await?;
         Ok(response.json().await?)
     }
 
+    pub async fn patch_season_monitored(
+        &self,
+        series_id: i32,
+        season_number: i32,
+        monitored: bool,
+    ) -> Result<()> {
+        let url = self.url(&format!("series/{series_id}/seasons/{season_number}"))?;
+        let body = json!({ "monitored": monitored });
+        trace_body("PATCH", &url, &body);
+        if crate::http::is_dry_run(self.dry_run, "PATCH", &url, &body) {
+            return Ok(());
+        }
+        self.send_with_retries(self.client.patch(url).json(&body))
+            .await?;
+        Ok(())
+    }
+
+    /// Send a request, retrying with doubling delays while Sonarr cannot be
+    /// reached or answers that it is temporarily unavailable, e.g. during a
+    /// restart. A POST that timed out may have been processed already, so
+    /// only connection errors are retried for those.
+    async fn send_with_retries(&self, request: reqwest::RequestBuilder) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let retry = request
+                .try_clone()
+                .ok_or_else(|| anyhow!("request cannot be retried"))?
+                .build()?;
+            let is_idempotent = retry.method() != Method::POST;
+            self.throttle().await;
+            let may_retry = attempt < self.request_retries;
+            match self.client.execute(retry).await {
+                Ok(response) => {
+                    let is_transient = matches!(
+                        response.status(),
+                        StatusCode::BAD_GATEWAY
+                            | StatusCode::SERVICE_UNAVAILABLE
+                            | StatusCode::GATEWAY_TIMEOUT
+                    );
+                    if !is_transient || !may_retry {
+                        return Ok(response.error_for_status()?);
+                    }
+                    debug!(status = %response.status(), attempt, "Sonarr is unavailable, retrying");
+                }
+                Err(e) if (e.is_connect() || (e.is_timeout() && is_idempotent)) && may_retry => {
+                    debug!(attempt, "Sonarr is unreachable, retrying: {e}");
+                }
+                Err(e) => return Err(e.into()),
+            }
+            tokio::time::sleep(self.retry_delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Wait `retry_delay * 2^attempt` before the next retry, up to `max_retry_delay`
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .retry_delay
+            .saturating_mul(2u32.saturating_pow(attempt));
+        self.max_retry_delay.map_or(delay, |max| delay.min(max))
+    }
+
+    /// Set the quality profile of `series` and return whether it changed.
+    pub fn set_quality_profile(series: &mut SeriesResource, profile_id: i32) -> bool {
+        let changed = series.quality_profile_id != Some(profile_id);
+        series.quality_profile_id = Some(profile_id);
+        changed
+    }
+
     pub async fn series(&self) -> Result<Vec<SeriesResource>> {
-        let series = self
-            .get::<Value>("series")
-            .await?
-            .as_array()
-            .ok_or_else(|| anyhow!("not an array"))?
+        let series = match self.get::<Value>("series").await? {
+            Value::Array(series) => series,
+            Value::Object(page) => self.series_pages(page).await?,
+            _ => bail!("not an array"),
+        };
+        let series = series
             .iter()
             .filter_map(|s| match serde_json::from_value(s.clone()) {
                 Ok(v) => Some(v),
@@ -88,6 +328,84 @@ impl Client {
         Ok(series)
     }
 
+    /// Collect all records of a paged series response, starting with `page`.
+    async fn series_pages(&self, mut page: Map<String, Value>) -> Result<Vec<Value>> {
+        let total = page
+            .get("totalRecords")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("not an array"))?;
+        let total = usize::try_from(total)?;
+        let page_size = page.get("pageSize").and_then(Value::as_u64);
+
+        let mut series = Vec::new();
+        let mut page_num = page.get("page").and_then(Value::as_u64).unwrap_or(1);
+        loop {
+            let Some(Value::Array(records)) = page.remove("records") else {
+                bail!("page {page_num} has no records");
+            };
+            let is_empty = records.is_empty();
+            let page_size = page_size.unwrap_or(records.len() as u64);
+            series.extend(records);
+
+            if is_empty || series.len() >= total {
+                break;
+            }
+
+            page_num += 1;
+            debug!(page = page_num, total, "fetching next page of series");
+            page = self
+                .get_query(
+                    "series",
+                    &[
+                        ("page", page_num.to_string()),
+                        ("pageSize", page_size.to_string()),
+                    ],
+                )
+                .await?;
+        }
+
+        Ok(series)
+    }
+
+    pub async fn commands(&self) -> Result<Vec<CommandResource>> {
+        self.get_query("command", &[("status", "queued,started".to_string())])
+            .await
+    }
+
+    /// Search the metadata provider for series matching `term`
+    pub async fn series_lookup(&self, term: &str) -> Result<Vec<SeriesLookupResource>> {
+        self.get_query("series/lookup", &[("term", term.to_string())])
+            .await
+    }
+
+    pub async fn episodes(&self, series_id: i32, season_num: i32) -> Result<Vec<EpisodeResource>> {
+        self.get_query(
+            "episode",
+            &[
+                ("seriesId", series_id.to_string()),
+                ("seasonNumber", season_num.to_string()),
+            ],
+        )
+        .await
+    }
+
+    /// Downloads in progress, grabbed but not yet imported
+    pub async fn queue(&self) -> Result<Vec<QueueResource>> {
+        self.get("queue/details").await
+    }
+
+    pub async fn quality_profiles(&self) -> Result<Vec<QualityProfileResource>> {
+        self.get("qualityprofile").await
+    }
+
+    pub async fn health(&self) -> Result<Vec<HealthCheckResult>> {
+        self.get("health").await
+    }
+
+    pub async fn tags(&self) -> Result<Vec<TagResource>> {
+        self.get("tag").await
+    }
+
     pub async fn search_season(
         &self,
         series: &SeriesResource,
@@ -99,11 +417,42 @@ impl Client {
         let season = series
             .season_mut(season_num)
             .ok_or_else(|| anyhow!("there is no season {season_num}"))?;
+        let season_monitored = season.monitored;
 
-        if !season.monitored || !series_monitored {
-            season.monitored = true;
-            series.monitored = true;
+        season.monitored = true;
+        series.monitored = true;
+
+        let patch_unsupported =
+            self.version == SonarrVersion::V2 || self.patch_unsupported.load(Ordering::Relaxed);
+        if !series_monitored || (!season_monitored && patch_unsupported) {
             self.put_series(&series).await?;
+        } else if !season_monitored {
+            // Not every version of Sonarr knows about this endpoint
+            if let Err(e) = self
+                .patch_season_monitored(series.id, season_num, true)
+                .await
+            {
+                debug!("patching the season failed, update the series from now on: {e}");
+                self.patch_unsupported.store(true, Ordering::Relaxed);
+                self.put_series(&series).await?;
+            }
+        }
+
+        match self.commands().await {
+            Ok(commands) => {
+                if commands
+                    .iter()
+                    .any(|c| c.is_pending() && c.is_season_search(series.id, season_num))
+                {
+                    debug!(
+                        series = series.id,
+                        season = season_num,
+                        "season search already in progress"
+                    );
+                    return Ok(Value::Null);
+                }
+            }
+            Err(e) => debug!("cannot list running commands: {e}"),
         }
 
         let cmd = json!({
@@ -112,25 +461,193 @@ impl Client {
             "seasonNumber": season_num,
         });
 
-        let mut url = self.base_url.clone();
-        url.path_segments_mut()
-            .map_err(|()| anyhow!("url is relative"))?
-            .push("api")
-            .push("v3")
-            .push("command");
+        let response = self.command(&cmd).await?;
+        if !self.search_timeout.is_zero() {
+            if let Some(id) = response
+                .get("id")
+                .and_then(Value::as_i64)
+                .and_then(|id| i32::try_from(id).ok())
+            {
+                // the search was sent either way, it just takes longer
+                if let Err(e) = self.wait_for_command(id, self.search_timeout).await {
+                    warn!(
+                        series = series.id,
+                        season = season_num,
+                        "Season search did not complete: {e}"
+                    );
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    /// Poll a command until Sonarr completed it or `timeout` elapsed
+    pub async fn wait_for_command(&self, command_id: i32, timeout: Duration) -> Result<()> {
+        tokio::time::timeout(timeout, self.poll_command(command_id))
+            .await
+            .map_err(|_| anyhow!("command {command_id} did not complete within {timeout:?}"))?
+    }
+
+    async fn poll_command(&self, command_id: i32) -> Result<()> {
+        loop {
+            let command: CommandResource = self.get(&format!("command/{command_id}")).await?;
+            if command.status == "completed" {
+                return Ok(());
+            }
+            if !command.is_pending() {
+                bail!("command {command_id} ended as {}", command.status);
+            }
+            trace!(id = command_id, status = %command.status, "waiting for command");
+            tokio::time::sleep(COMMAND_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Search all missing episodes of all monitored series
+    pub async fn search_all_missing(&self) -> Result<()> {
+        self.command(&json!({ "name": "MissingEpisodeSearch" }))
+            .await?;
+        Ok(())
+    }
+
+    /// Hand a release found by an indexer to Sonarr to download it for an episode
+    pub async fn release_push(
+        &self,
+        guid: &str,
+        indexer_id: i32,
+        episode_id: i32,
+    ) -> Result<Value> {
+        let url = self.url("release/push")?;
+        let body = json!({
+            "guid": guid,
+            "indexerId": indexer_id,
+            "episodeId": episode_id,
+        });
+        trace_body("POST", &url, &body);
+        if crate::http::is_dry_run(self.dry_run, "POST", &url, &body) {
+            return Ok(Value::Null);
+        }
+        let response = self
+            .send_with_retries(self.client.post(url).json(&body))
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Let Sonarr rescan the files of a series on disk
+    pub async fn rescan_series(&self, series_id: i32) -> Result<()> {
+        self.command(&json!({ "name": "RescanSeries", "seriesId": series_id }))
+            .await?;
+        Ok(())
+    }
+
+    /// Let Sonarr rename the files of a series to its naming scheme
+    pub async fn rename_series(&self, series_id: i32) -> Result<Value> {
+        self.command(&json!({ "name": "RenameSeries", "seriesIds": [series_id] }))
+            .await
+    }
+
+    async fn command(&self, cmd: &Value) -> Result<serde_json::Value> {
+        let url = self.url("command")?;
 
+        trace_body("POST", &url, cmd);
+        if crate::http::is_dry_run(self.dry_run, "POST", &url, cmd) {
+            return Ok(Value::Null);
+        }
         let response = self
-            .client
-            .post(url)
-            .json(&cmd)
-            .send()
-            .await?
-            .error_for_status()?;
+            .send_with_retries(self.client.post(url).json(cmd))
+            .await?;
 
         Ok(response.json().await?)
     }
 }
 
+fn trace_body(method: &str, url: &Url, body: &impl Serialize) {
+    if tracing::enabled!(tracing::Level::TRACE) {
+        match serde_json::to_string(body) {
+            Ok(json) => trace!(body = %json, %url, "{method}"),
+            Err(e) => trace!(%url, "{method} with unserializable body: {e}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityProfileResource {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheckResult {
+    pub r#type: String,
+    pub message: String,
+}
+
+impl HealthCheckResult {
+    pub fn is_problem(&self) -> bool {
+        matches!(self.r#type.as_str(), "warning" | "error")
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagResource {
+    pub id: i32,
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandResource {
+    pub name: String,
+    pub status: String,
+    #[serde(default)]
+    pub body: Value,
+}
+
+impl CommandResource {
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status.as_str(), "queued" | "started")
+    }
+
+    pub fn is_season_search(&self, series_id: i32, season_num: i32) -> bool {
+        self.name == "SeasonSearch"
+            && self.body.get("seriesId").and_then(Value::as_i64) == Some(series_id.into())
+            && self.body.get("seasonNumber").and_then(Value::as_i64) == Some(season_num.into())
+    }
+}
+
+/// Search result of a series that may or may not be in the library
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesLookupResource {
+    pub title: String,
+    pub year: Option<i32>,
+    pub tvdb_id: i32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpisodeResource {
+    pub episode_number: i32,
+    pub has_file: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueResource {
+    pub series_id: Option<i32>,
+    pub episode_id: Option<i32>,
+    pub season_number: Option<i32>,
+    pub status: String,
+}
+
+impl QueueResource {
+    pub fn is_season(&self, series_id: i32, season_num: i32) -> bool {
+        self.series_id == Some(series_id) && self.season_number == Some(season_num)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SeasonStatisticsResource {
@@ -170,16 +687,35 @@ pub enum NewItemMonitorTypes {
 pub struct SeriesResource {
     pub id: i32,
     pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_slug: Option<String>,
     pub tvdb_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imdb_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmdb_id: Option<i32>,
+    /// E.g. "continuing", "upcoming" or "ended"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
     pub monitored: bool,
     // optional for v3 compatibility
     pub monitor_new_items: Option<NewItemMonitorTypes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality_profile_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<i32>>,
     pub seasons: Vec<SeasonResource>,
     #[serde(flatten)]
     other: serde_json::Value,
 }
 
 impl SeriesResource {
+    pub fn has_ended(&self) -> bool {
+        self.status
+            .as_deref()
+            .is_some_and(|s| s.eq_ignore_ascii_case("ended"))
+    }
+
     pub fn season(&self, num: i32) -> Option<&SeasonResource> {
         self.seasons.iter().find(|s| s.season_number == num)
     }
@@ -191,11 +727,11 @@ impl SeriesResource {
 
 #[cfg(test)]
 mod test {
-    use httpmock::Method::{POST, PUT};
+    use httpmock::Method::{GET, PATCH, POST, PUT};
     use serde_json::{json, Value};
 
     use crate::sonarr::{
-        NewItemMonitorTypes, SeasonResource, SeasonStatisticsResource, SeriesResource,
+        NewItemMonitorTypes, Options, SeasonResource, SeasonStatisticsResource, SeriesResource,
     };
 
     #[tokio::test]
@@ -209,11 +745,100 @@ mod test {
                 then.json_body(serde_json::json!([]));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let _ = client.series().await?;
+
+        series_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cookies() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series")
+                    .header("Cookie", "cf_clearance=token");
+                then.json_body(serde_json::json!([]));
+            })
+            .await;
+        let options = Options {
+            cookies: [("cf_clearance".to_string(), "token".to_string())].into(),
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        let _ = client.series().await?;
+
+        series_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn base_url() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let probe_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api");
+                then.json_body(json!({}));
+            })
+            .await;
+
+        for base_url in ["/pathprefix", "/pathprefix/"] {
+            let mut client =
+                super::Client::new(&server.url(base_url), "secret", &Options::default())?;
+            client.probe().await?;
+        }
+
+        probe_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn v2() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let probe_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api");
+                then.body("Sonarr API");
+            })
+            .await;
+        let status_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/system/status");
+                then.json_body(json!({ "version": "2.0.0.5344" }));
+            })
+            .await;
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/series");
+                then.json_body(json!([]));
+            })
+            .await;
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
 
+        let mut client =
+            super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+        client.probe().await?;
         let _ = client.series().await?;
+        client.search_all_missing().await?;
 
+        probe_mock.assert_async().await;
+        status_mock.assert_async().await;
         series_mock.assert_async().await;
+        command_mock.assert_async().await;
 
         Ok(())
     }
@@ -236,7 +861,7 @@ mod test {
                 ));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
 
         let series = client.series().await?;
         assert_eq!(series[0].id, 1234);
@@ -272,7 +897,7 @@ mod test {
                 ));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
 
         let series = client.series().await?;
         assert_eq!(series.len(), 2);
@@ -304,7 +929,7 @@ mod test {
                 ));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
 
         let series = client.series().await?;
         assert_eq!(series.len(), 1);
@@ -335,7 +960,7 @@ mod test {
                 ));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
 
         let series = client.series().await?;
         assert_eq!(series.len(), 1);
@@ -345,6 +970,61 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn series_paged() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let series = |id: i32| {
+            json!({
+                "id": id,
+                "title": "TestShow",
+                "tvdbId": 5678,
+                "monitored": false,
+                "monitorNewItems": "all",
+                "seasons": []
+            })
+        };
+
+        // registered first so it takes precedence over the unpaged request
+        let second_page_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series")
+                    .query_param("page", "2")
+                    .query_param("pageSize", "2");
+                then.json_body(json!({
+                    "page": 2,
+                    "pageSize": 2,
+                    "totalRecords": 3,
+                    "records": [series(3)]
+                }));
+            })
+            .await;
+
+        let first_page_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series");
+                then.json_body(json!({
+                    "page": 1,
+                    "pageSize": 2,
+                    "totalRecords": 3,
+                    "records": [series(1), series(2)]
+                }));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let series = client.series().await?;
+        assert_eq!(
+            series.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        first_page_mock.assert_async().await;
+        second_page_mock.assert_async().await;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn series_emtpy() -> Result<(), Box<dyn std::error::Error>> {
         let server = httpmock::MockServer::start_async().await;
@@ -355,7 +1035,7 @@ mod test {
                 then.json_body(serde_json::json!([]));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
 
         let series = client.series().await?;
         assert_eq!(series.len(), 0);
@@ -372,9 +1052,15 @@ mod test {
         let series = SeriesResource {
             id: 1234,
             title: Some("TestShow".to_string()),
+            title_slug: None,
             tvdb_id: 5678,
+            imdb_id: None,
+            tmdb_id: None,
+            status: None,
             monitored: false,
             monitor_new_items: Some(NewItemMonitorTypes::All),
+            quality_profile_id: None,
+            tags: None,
             seasons: vec![],
             other: Value::Null,
         };
@@ -396,7 +1082,7 @@ mod test {
                 then.json_body(json!({}));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
 
         client.put_series(&series).await?;
 
@@ -426,9 +1112,15 @@ mod test {
         let series = SeriesResource {
             id: 1234,
             title: Some("TestShow".to_string()),
+            title_slug: None,
             tvdb_id: 5678,
+            imdb_id: None,
+            tmdb_id: None,
+            status: None,
             monitored: false,
             monitor_new_items: Some(NewItemMonitorTypes::All),
+            quality_profile_id: None,
+            tags: None,
             seasons: vec![season],
             other: serde_json::json!({}),
         };
@@ -472,7 +1164,7 @@ mod test {
                 then.json_body(json!({}));
             })
             .await;
-        let client = super::Client::new(&server.url("/pathprefix"), "secret")?;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
 
         client.search_season(&series, 1).await?;
 
@@ -481,4 +1173,737 @@ mod test {
 
         Ok(())
     }
+
+    fn monitored_series() -> SeriesResource {
+        let season = SeasonResource {
+            season_number: 1,
+            monitored: false,
+            statistics: None,
+            other: Value::Null,
+        };
+
+        SeriesResource {
+            id: 1234,
+            title: Some("TestShow".to_string()),
+            title_slug: None,
+            tvdb_id: 5678,
+            imdb_id: None,
+            tmdb_id: None,
+            status: None,
+            monitored: true,
+            monitor_new_items: Some(NewItemMonitorTypes::All),
+            quality_profile_id: None,
+            tags: None,
+            seasons: vec![season],
+            other: serde_json::json!({}),
+        }
+    }
+
+    #[tokio::test]
+    async fn search_season_patch() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let patch_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234/seasons/1")
+                    .method(PATCH)
+                    .json_body(json!({ "monitored": true }));
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234").method(PUT);
+                then.json_body(json!({}));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        client.search_season(&monitored_series(), 1).await?;
+
+        patch_mock.assert_async().await;
+        series_mock.assert_hits_async(0).await;
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_season_patch_fallback() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let patch_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234/seasons/1")
+                    .method(PATCH);
+                then.status(405);
+            })
+            .await;
+
+        let series_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234")
+                    .method(PUT)
+                    .json_body(serde_json::json!(
+                        {
+                            "id": 1234,
+                            "title": "TestShow",
+                            "tvdbId": 5678,
+                            "monitored": true,
+                            "monitorNewItems": "all",
+                            "seasons": [{
+                                "seasonNumber": 1,
+                                "monitored": true,
+                                "statistics": null
+                            }]
+                        }
+                    ));
+                then.json_body(json!({}));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        client.search_season(&monitored_series(), 1).await?;
+        client.clone().search_season(&monitored_series(), 1).await?;
+
+        patch_mock.assert_async().await;
+        series_mock.assert_hits_async(2).await;
+        command_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_season_in_progress() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let commands_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(GET);
+                then.json_body(json!([{
+                    "name": "SeasonSearch",
+                    "status": "started",
+                    "body": {
+                        "seriesId": 1234,
+                        "seasonNumber": 1,
+                    }
+                }, {
+                    "name": "SeasonSearch",
+                    "status": "queued",
+                    "body": {
+                        "seriesId": 1234,
+                        "seasonNumber": 2,
+                    }
+                }]));
+            })
+            .await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let _patch_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234/seasons/1")
+                    .method(PATCH);
+                then.json_body(json!({}));
+            })
+            .await;
+
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        client.search_season(&monitored_series(), 1).await?;
+
+        commands_mock.assert_async().await;
+        command_mock.assert_hits_async(0).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_season_wait() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let _patch_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234/seasons/1")
+                    .method(PATCH);
+                then.json_body(json!({}));
+            })
+            .await;
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({ "id": 7, "name": "SeasonSearch", "status": "queued" }));
+            })
+            .await;
+        let status_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command/7").method(GET);
+                then.json_body(json!({ "id": 7, "name": "SeasonSearch", "status": "completed" }));
+            })
+            .await;
+
+        let options = Options {
+            search_timeout: std::time::Duration::from_secs(5),
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        client.search_season(&monitored_series(), 1).await?;
+
+        command_mock.assert_async().await;
+        status_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_season_wait_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let _patch_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1234/seasons/1")
+                    .method(PATCH);
+                then.json_body(json!({}));
+            })
+            .await;
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({ "id": 7, "name": "SeasonSearch", "status": "queued" }));
+            })
+            .await;
+        let _status_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command/7").method(GET);
+                then.json_body(json!({ "id": 7, "name": "SeasonSearch", "status": "started" }));
+            })
+            .await;
+
+        let options = Options {
+            search_timeout: std::time::Duration::from_millis(100),
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        // the search was sent, even if it takes longer than we wait
+        let response = client.search_season(&monitored_series(), 1).await?;
+        assert_eq!(response.get("id"), Some(&json!(7)));
+
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wait_for_command_failed() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let status_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command/7").method(GET);
+                then.json_body(json!({ "id": 7, "name": "SeasonSearch", "status": "failed" }));
+            })
+            .await;
+
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let result = client
+            .wait_for_command(7, std::time::Duration::from_secs(5))
+            .await;
+
+        assert!(result.is_err());
+        status_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_all_missing() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command")
+                    .method(POST)
+                    .json_body(json!({ "name": "MissingEpisodeSearch" }));
+                then.json_body(json!({}));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        client.search_all_missing().await?;
+
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rescan_series() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command")
+                    .method(POST)
+                    .json_body(json!({ "name": "RescanSeries", "seriesId": 1234 }));
+                then.json_body(json!({}));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        client.rescan_series(1234).await?;
+
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_series() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command")
+                    .method(POST)
+                    .json_body(json!({ "name": "RenameSeries", "seriesIds": [1234] }));
+                then.json_body(json!({ "id": 5 }));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let response = client.rename_series(1234).await?;
+        assert_eq!(response, json!({ "id": 5 }));
+
+        command_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limit() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let tags_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/tag");
+                then.json_body(json!([]));
+            })
+            .await;
+        let options = Options {
+            rate_limit: Some(10.0),
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        let start = std::time::Instant::now();
+        for _ in 0..3 {
+            client.clone().tags().await?;
+        }
+        assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+
+        tags_mock.assert_hits_async(3).await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_rate_limit() {
+        for rate_limit in [0.0, -1.0, f64::NAN] {
+            let options = Options {
+                rate_limit: Some(rate_limit),
+                ..Options::default()
+            };
+            assert!(super::Client::new("http://localhost", "secret", &options).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn request_timeout() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let tags_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/tag");
+                then.delay(std::time::Duration::from_secs(1))
+                    .json_body(json!([]));
+            })
+            .await;
+        let options = Options {
+            request_timeout: Some(std::time::Duration::from_millis(100)),
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        assert!(client.tags().await.is_err());
+
+        tags_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn health() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let health_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/health");
+                then.json_body(json!([{
+                    "source": "IndexerRssCheck",
+                    "type": "warning",
+                    "message": "Indexer RSS sync is not configured",
+                    "wikiUrl": "https://wiki.servarr.com",
+                },{
+                    "source": "UpdateCheck",
+                    "type": "notice",
+                    "message": "New update is available",
+                }]));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let health = client.health().await?;
+        let problems = health
+            .iter()
+            .filter(|h| h.is_problem())
+            .map(|h| h.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(problems, ["Indexer RSS sync is not configured"]);
+
+        health_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn command_retries() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.status(503);
+            })
+            .await;
+        let options = Options {
+            request_retries: 1,
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        assert!(client.search_all_missing().await.is_err());
+
+        command_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn timeout_retries() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let patch_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/1/seasons/2")
+                    .method(PATCH);
+                then.json_body(json!({}))
+                    .delay(std::time::Duration::from_millis(500));
+            })
+            .await;
+        let options = Options {
+            request_retries: 1,
+            request_timeout: Some(std::time::Duration::from_millis(100)),
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        assert!(client.patch_season_monitored(1, 2, true).await.is_err());
+
+        patch_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn retry_delay() -> Result<(), Box<dyn std::error::Error>> {
+        let options = Options {
+            retry_delay: std::time::Duration::from_secs(2),
+            ..Options::default()
+        };
+        let client = super::Client::new("http://localhost", "secret", &options)?;
+        let delays = (0..4).map(|attempt| client.retry_delay(attempt).as_secs());
+        assert_eq!(delays.collect::<Vec<_>>(), [2, 4, 8, 16]);
+
+        let options = Options {
+            retry_delay: std::time::Duration::from_secs(2),
+            max_retry_delay: Some(std::time::Duration::from_secs(5)),
+            ..Options::default()
+        };
+        let client = super::Client::new("http://localhost", "secret", &options)?;
+        let delays = (0..4).map(|attempt| client.retry_delay(attempt).as_secs());
+        assert_eq!(delays.collect::<Vec<_>>(), [2, 4, 5, 5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn command_timeout_no_retry() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let command_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(POST);
+                then.json_body(json!({}))
+                    .delay(std::time::Duration::from_millis(500));
+            })
+            .await;
+        let options = Options {
+            request_retries: 1,
+            request_timeout: Some(std::time::Duration::from_millis(100)),
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        // Sonarr may still run the command, sending it again could run it twice
+        assert!(client.search_all_missing().await.is_err());
+
+        command_mock.assert_hits_async(1).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_retries() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let queue_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/queue/details").method(GET);
+                then.status(503);
+            })
+            .await;
+        let options = Options {
+            request_retries: 1,
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        assert!(client.queue().await.is_err());
+
+        queue_mock.assert_hits_async(2).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn release_push() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let push_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/release/push")
+                    .method(POST)
+                    .json_body(json!({
+                        "guid": "abc-123",
+                        "indexerId": 1,
+                        "episodeId": 123,
+                    }));
+                then.json_body(json!([{ "approved": true }]));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        client.release_push("abc-123", 1, 123).await?;
+
+        push_mock.assert_async().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn series_lookup() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let lookup_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/series/lookup")
+                    .query_param("term", "Test Show");
+                then.json_body(json!([{
+                    "title": "The Test Show",
+                    "year": 2020,
+                    "tvdbId": 5678,
+                    "seasons": []
+                }]));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let results = client.series_lookup("Test Show").await?;
+
+        lookup_mock.assert_async().await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "The Test Show");
+        assert_eq!(results[0].year, Some(2020));
+        assert_eq!(results[0].tvdb_id, 5678);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queue() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let queue_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/queue/details").method(GET);
+                then.json_body(json!([{
+                    "id": 1,
+                    "seriesId": 1234,
+                    "episodeId": 42,
+                    "seasonNumber": 2,
+                    "status": "downloading",
+                    "title": "Test.Show.S02E01"
+                }]));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let queue = client.queue().await?;
+
+        queue_mock.assert_async().await;
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].episode_id, Some(42));
+        assert_eq!(queue[0].status, "downloading");
+        assert!(queue[0].is_season(1234, 2));
+        assert!(!queue[0].is_season(1234, 1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quality_profiles() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let profiles_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/qualityprofile").method(GET);
+                then.json_body(json!([
+                    { "id": 1, "name": "Any", "upgradeAllowed": false },
+                    { "id": 4, "name": "HD-1080p", "upgradeAllowed": true },
+                ]));
+            })
+            .await;
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &Options::default())?;
+
+        let profiles = client.quality_profiles().await?;
+
+        profiles_mock.assert_async().await;
+        assert_eq!(
+            profiles
+                .iter()
+                .map(|p| (p.id, p.name.as_str()))
+                .collect::<Vec<_>>(),
+            [(1, "Any"), (4, "HD-1080p")]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dry_run() -> Result<(), Box<dyn std::error::Error>> {
+        let server = httpmock::MockServer::start_async().await;
+
+        let commands_mock = server
+            .mock_async(|when, then| {
+                when.path("/pathprefix/api/v3/command").method(GET);
+                then.json_body(json!([]));
+            })
+            .await;
+        let patch_mock = server
+            .mock_async(|when, then| {
+                when.method(PATCH);
+                then.json_body(json!({}));
+            })
+            .await;
+        let put_mock = server
+            .mock_async(|when, then| {
+                when.method(PUT);
+                then.json_body(json!({}));
+            })
+            .await;
+        let post_mock = server
+            .mock_async(|when, then| {
+                when.method(POST);
+                then.json_body(json!({}));
+            })
+            .await;
+        let options = Options {
+            dry_run: true,
+            ..Options::default()
+        };
+        let client = super::Client::new(&server.url("/pathprefix"), "secret", &options)?;
+
+        let mut series = monitored_series();
+        client.search_season(&series, 1).await?;
+        series.monitored = false;
+        client.search_season(&series, 1).await?;
+        client.search_all_missing().await?;
+
+        commands_mock.assert_hits_async(2).await;
+        patch_mock.assert_hits_async(0).await;
+        put_mock.assert_hits_async(0).await;
+        post_mock.assert_hits_async(0).await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_quality_profile() {
+        let mut series = monitored_series();
+
+        assert!(super::Client::set_quality_profile(&mut series, 2));
+        assert_eq!(series.quality_profile_id, Some(2));
+        assert!(!super::Client::set_quality_profile(&mut series, 2));
+    }
+
+    #[test]
+    fn imdb_id() -> Result<(), Box<dyn std::error::Error>> {
+        let series: SeriesResource = serde_json::from_value(json!({
+            "id": 1234,
+            "title": "TestShow",
+            "tvdbId": 5678,
+            "imdbId": "tt0123456",
+            "monitored": true,
+            "seasons": [],
+        }))?;
+        assert_eq!(series.imdb_id.as_deref(), Some("tt0123456"));
+
+        let value = serde_json::to_value(&series)?;
+        assert_eq!(value["imdbId"], json!("tt0123456"));
+
+        Ok(())
+    }
 }